@@ -16,6 +16,7 @@ const WALL: &str = "⬜";
 const ROWS: usize = 5;
 const COLS: usize = 6;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BoardTile {
     EMPTY,
@@ -56,7 +57,7 @@ impl BoardState {
 
         let col_vals = &mut self.smap;
         (0..COLS).for_each(|c| {
-            col_vals.insert(c, 4 as u8);
+            col_vals.insert(c, 4);
         });
 
         self.info = None;
@@ -77,7 +78,7 @@ impl BoardState {
 
         let col_vals = &mut self.smap;
         (0..COLS).for_each(|c| {
-            col_vals.insert(c, 0 as u8);
+            col_vals.insert(c, 0);
         });
         self.check_winner()
     }
@@ -117,7 +118,7 @@ impl BoardState {
             }
         }
         if 0 == others_count {
-            self.info = Some(format!("No winner."));
+            self.info = Some("No winner.".to_owned());
         }
 
         let mut c_d_count = 0;
@@ -163,10 +164,10 @@ impl Display for BoardState {
             for c in 0..COLS {
                 write!(&mut b, "{}", self.grid[r][c])?;
             }
-            write!(&mut b, "\n")?;
+            writeln!(&mut b)?;
         }
         if let Some(info) = &self.info {
-            write!(&mut b, "{}\n", info)?;
+            writeln!(&mut b, "{}", info)?;
         }
         write!(f, "{}", b)
     }
@@ -199,11 +200,11 @@ pub async fn place(
     Path((team, column)): Path<(String, u8)>,
     Extension(state): Extension<Arc<RwLock<BoardState>>>,
 ) -> impl IntoResponse {
-    let ref team_str = team.as_str();
+    let team_str = &team.as_str();
     if ![TEAM_COKY, TEAM_MILK].contains(team_str) {
         return (StatusCode::BAD_REQUEST, "".to_owned());
     }
-    if 1 > column || column > 4 {
+    if !(1..=4).contains(&column) {
         return (StatusCode::BAD_REQUEST, "".to_owned());
     }
     let mut board_state = state.write().unwrap();
@@ -211,7 +212,7 @@ pub async fn place(
     if smap_col_next_val == 0 {
         return (StatusCode::SERVICE_UNAVAILABLE, "".to_owned());
     }
-    if let Some(_) = &board_state.info {
+    if board_state.info.is_some() {
         return (StatusCode::SERVICE_UNAVAILABLE, "".to_owned());
     }
     let board_grid = &mut board_state.grid;
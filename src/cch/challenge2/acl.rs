@@ -0,0 +1,297 @@
+use std::{env, net::IpAddr, sync::Arc};
+
+use axum::{extract::Query, response::IntoResponse, Extension, Json};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{client::ClientAddr, notation::Notation, IpOpsError};
+
+/// Comma-separated rules such as `allow 10.0.0.0/8, deny ::/0`, first match wins.
+const RULES_VAR: &str = "CCH_ACL";
+/// `allow` (the default) or `deny`, for addresses no rule matches.
+const DEFAULT_VAR: &str = "CCH_ACL_DEFAULT";
+/// Rules for the admin routes, in the same syntax; anything they do not
+/// allow is denied.
+const ADMIN_RULES_VAR: &str = "CCH_ADMIN_ACL";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+impl Action {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "allow" => Some(Self::Allow),
+            "deny" => Some(Self::Deny),
+            _ => None,
+        }
+    }
+    fn name(self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Deny => "deny",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    action: Action,
+    net: IpNet,
+}
+impl Rule {
+    /// Reads `allow <cidr>` or `deny <cidr>`; a bare address is a /32 or /128.
+    fn parse(rule: &str) -> Option<Self> {
+        let (action, net) = rule.trim().split_once(char::is_whitespace)?;
+        let net = net.trim();
+        Some(Self {
+            action: Action::parse(action)?,
+            net: net
+                .parse::<IpNet>()
+                .or_else(|_| net.parse::<IpAddr>().map(IpNet::from))
+                .ok()?
+                .trunc(),
+        })
+    }
+}
+
+/// The outcome of checking an address, with the rule that decided it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict<'a> {
+    pub action: Action,
+    pub rule: Option<(usize, &'a Rule)>,
+}
+impl Verdict<'_> {
+    pub fn is_allowed(&self) -> bool {
+        self.action == Action::Allow
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "action": self.action.name(),
+            "rule": self.rule.map(|(index, rule)| json!({
+                "index": index,
+                "action": rule.action.name(),
+                "cidr": rule.net.to_string(),
+            })),
+        })
+    }
+}
+
+/// An ordered list of allow/deny rules guarding the sensitive routes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessList {
+    rules: Vec<Rule>,
+    default: Action,
+}
+impl Default for AccessList {
+    fn default() -> Self {
+        Self {
+            rules: vec![],
+            default: Action::Allow,
+        }
+    }
+}
+impl AccessList {
+    pub fn parse(rules: &str, default: &str) -> Option<Self> {
+        let rules = rules
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(Rule::parse)
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            rules,
+            default: Action::parse(default)?,
+        })
+    }
+
+    /// Reads the rules from `CCH_ACL` and `CCH_ACL_DEFAULT`. Bad values are
+    /// reported and deny everything rather than silently opening up.
+    pub fn shared_from_env() -> Arc<Self> {
+        let rules = env::var(RULES_VAR).unwrap_or_default();
+        let default = env::var(DEFAULT_VAR).unwrap_or("allow".to_owned());
+        match Self::parse(&rules, &default) {
+            Some(access_list) => Arc::new(access_list),
+            None => {
+                tracing::warn!(
+                    "ignoring invalid {} / {}, denying everything",
+                    RULES_VAR,
+                    DEFAULT_VAR
+                );
+                Arc::new(Self {
+                    rules: vec![],
+                    default: Action::Deny,
+                })
+            }
+        }
+    }
+
+    /// The first rule containing `addr` decides; IPv4-mapped addresses are
+    /// checked as the IPv4 address they carry. An unknown address only gets
+    /// the default.
+    pub fn check(&self, addr: Option<IpAddr>) -> Verdict<'_> {
+        let rule = addr.map(|addr| addr.to_canonical()).and_then(|addr| {
+            self.rules
+                .iter()
+                .enumerate()
+                .find(|(_, rule)| rule.net.contains(&addr))
+        });
+        Verdict {
+            action: rule.map_or(self.default, |(_, rule)| rule.action),
+            rule,
+        }
+    }
+}
+
+/// The access list of the admin routes. Unlike `AccessList` it denies by
+/// default, so that without `CCH_ADMIN_ACL` nobody gets in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminAccess(AccessList);
+impl AdminAccess {
+    fn parse(rules: &str) -> Option<Self> {
+        AccessList::parse(rules, "deny").map(Self)
+    }
+
+    pub fn shared_from_env() -> Arc<Self> {
+        let rules = env::var(ADMIN_RULES_VAR).unwrap_or_default();
+        let admin_access = Self::parse(&rules).unwrap_or_else(|| {
+            tracing::warn!("ignoring invalid {}, denying everything", ADMIN_RULES_VAR);
+            Self(AccessList {
+                rules: vec![],
+                default: Action::Deny,
+            })
+        });
+        Arc::new(admin_access)
+    }
+
+    pub fn check(&self, addr: Option<IpAddr>) -> Verdict<'_> {
+        self.0.check(addr)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AclParams {
+    addr: Option<String>,
+    #[serde(rename = "in")]
+    input: Option<String>,
+}
+
+fn check_addr(
+    access_list: &AccessList,
+    acl_params: &AclParams,
+    client_addr: Option<IpAddr>,
+) -> Result<Value, IpOpsError> {
+    let addr = match acl_params.addr.as_deref() {
+        Some(value) => {
+            let notation = Notation::parse("in", acl_params.input.as_deref())?;
+            match notation.read(value) {
+                Some((addr, None)) => addr,
+                _ => {
+                    return Err(IpOpsError::InvalidAddress {
+                        param: "addr",
+                        value: value.to_owned(),
+                    })
+                }
+            }
+        }
+        None => client_addr.ok_or(IpOpsError::MissingOperand { params: &["addr"] })?,
+    };
+    let mut report = access_list.check(Some(addr)).to_json();
+    report["addr"] = json!(addr.to_string());
+    report["default"] = json!(access_list.default.name());
+    Ok(report)
+}
+
+/// Explains which rule decides `addr`, or the caller's own address.
+pub async fn check_acl(
+    client_addr: ClientAddr,
+    acl_params: Query<AclParams>,
+    Extension(access_list): Extension<Arc<AccessList>>,
+) -> Result<impl IntoResponse, IpOpsError> {
+    Ok(Json(check_addr(
+        &access_list,
+        &acl_params,
+        client_addr.addr,
+    )?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let access_list = AccessList::parse(
+            "allow 10.1.0.0/16, deny 10.0.0.0/8 ,allow 2001:db8::/32, deny ::/0",
+            "deny",
+        )
+        .unwrap();
+        let verdict = access_list.check(addr("10.1.2.3"));
+        assert!(verdict.is_allowed());
+        assert_eq!(Some(0), verdict.rule.map(|(index, _)| index));
+        let verdict = access_list.check(addr("10.2.2.3"));
+        assert!(!verdict.is_allowed());
+        assert_eq!(Some(1), verdict.rule.map(|(index, _)| index));
+        assert!(access_list.check(addr("2001:db8::1")).is_allowed());
+        assert_eq!(3, access_list.check(addr("fe80::1")).rule.unwrap().0);
+        // Nothing matches, so the default decides.
+        let verdict = access_list.check(addr("192.0.2.1"));
+        assert!(!verdict.is_allowed());
+        assert_eq!(None, verdict.rule);
+        assert!(!access_list.check(None).is_allowed());
+    }
+
+    #[test]
+    fn test_mapped_addresses() {
+        let access_list = AccessList::parse("deny 192.0.2.0/24", "allow").unwrap();
+        assert!(!access_list.check(addr("::ffff:192.0.2.7")).is_allowed());
+        assert!(access_list.check(addr("::ffff:198.51.100.7")).is_allowed());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some(AccessList::default()), AccessList::parse(" ", "Allow"));
+        assert_eq!(None, AccessList::parse("permit 10.0.0.0/8", "allow"));
+        assert_eq!(None, AccessList::parse("allow 10.0.0.0/33", "allow"));
+        assert_eq!(None, AccessList::parse("allow", "allow"));
+        assert_eq!(None, AccessList::parse("", "maybe"));
+        let access_list = AccessList::parse("deny 10.9.9.9/8, allow fd00::1", "allow").unwrap();
+        assert_eq!("10.0.0.0/8", access_list.rules[0].net.to_string());
+        assert_eq!("fd00::1/128", access_list.rules[1].net.to_string());
+    }
+
+    #[test]
+    fn test_check_report() {
+        let access_list = AccessList::parse("deny 10.0.0.0/8", "allow").unwrap();
+        let params = AclParams {
+            addr: Some("167772161".to_owned()),
+            input: Some("int".to_owned()),
+        };
+        let report = check_addr(&access_list, &params, None).unwrap();
+        assert_eq!("10.0.0.1", report["addr"]);
+        assert_eq!("deny", report["action"]);
+        assert_eq!("10.0.0.0/8", report["rule"]["cidr"]);
+        let report = check_addr(&access_list, &AclParams::default(), addr("1.1.1.1")).unwrap();
+        assert_eq!(Value::Null, report["rule"]);
+        assert_eq!("allow", report["action"]);
+        let err = check_addr(&access_list, &AclParams::default(), None).unwrap_err();
+        assert_eq!("missing_operand", err.code());
+    }
+
+    #[test]
+    fn test_admin_access() {
+        let admin_access = AdminAccess::parse("").unwrap();
+        assert!(!admin_access.check(addr("127.0.0.1")).is_allowed());
+        let admin_access = AdminAccess::parse("allow 127.0.0.1, deny 10.0.0.0/8").unwrap();
+        assert!(admin_access.check(addr("::ffff:127.0.0.1")).is_allowed());
+        assert!(!admin_access.check(addr("192.168.0.1")).is_allowed());
+        assert!(!admin_access.check(None).is_allowed());
+        assert_eq!(None, AdminAccess::parse("permit 127.0.0.1"));
+    }
+}
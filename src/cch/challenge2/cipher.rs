@@ -0,0 +1,371 @@
+use std::net::IpAddr;
+
+use super::{IPParams, IpOpsError};
+
+/// The arithmetic applied between the address and the key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpOp {
+    Add,
+    Sub,
+    Xor,
+    Rotate,
+}
+impl IpOp {
+    pub const NAMES: &'static [&'static str] = &["add", "sub", "xor", "rotate"];
+
+    pub fn parse(value: &str) -> Result<Self, IpOpsError> {
+        match value {
+            "add" => Ok(Self::Add),
+            "sub" => Ok(Self::Sub),
+            "xor" => Ok(Self::Xor),
+            "rotate" => Ok(Self::Rotate),
+            _ => Err(IpOpsError::InvalidParameter {
+                param: "op",
+                value: value.to_owned(),
+                expected: Self::NAMES,
+            }),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Xor => "xor",
+            Self::Rotate => "rotate",
+        }
+    }
+}
+
+/// How an address is cut into the units the operation works on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpMode {
+    /// 8-bit units, the way IPv4 has always been handled.
+    Octet,
+    /// 16-bit units, the way IPv6 has always been handled.
+    Group,
+    /// The whole address as a single 32 or 128-bit integer.
+    Int,
+}
+impl IpMode {
+    pub const NAMES: &'static [&'static str] = &["octet", "group", "int"];
+
+    pub fn parse(value: &str) -> Result<Self, IpOpsError> {
+        match value {
+            "octet" => Ok(Self::Octet),
+            "group" => Ok(Self::Group),
+            "int" => Ok(Self::Int),
+            _ => Err(IpOpsError::InvalidParameter {
+                param: "mode",
+                value: value.to_owned(),
+                expected: Self::NAMES,
+            }),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Octet => "octet",
+            Self::Group => "group",
+            Self::Int => "int",
+        }
+    }
+    fn unit_bits(self, width: u32) -> u32 {
+        match self {
+            Self::Octet => 8,
+            Self::Group => 16,
+            Self::Int => width,
+        }
+    }
+}
+
+fn unit_mask(bits: u32) -> u128 {
+    if bits >= u128::BITS {
+        u128::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+fn rotate_left(value: u128, by: u32, bits: u32) -> u128 {
+    let by = by % bits;
+    if by == 0 {
+        value
+    } else {
+        ((value << by) | (value >> (bits - by))) & unit_mask(bits)
+    }
+}
+
+/// A reversible transformation of `width`-bit addresses (32 for IPv4, 128
+/// for IPv6) under a key of the same width. The `/2` routes only ever talk to
+/// this trait, so new schemes plug in through [`cipher_by_name`].
+pub trait AddressCipher: Send + Sync {
+    fn name(&self) -> String;
+
+    /// Computes the destination of `from` under `key`.
+    fn encrypt(&self, width: u32, from: u128, key: u128) -> u128;
+
+    /// Recovers the address that `encrypt` turned into `to` under `key`.
+    fn decrypt(&self, width: u32, to: u128, key: u128) -> u128;
+
+    /// Finds a key that makes `from` encrypt to `to`, if there is one.
+    fn derive_key(&self, width: u32, from: u128, to: u128) -> Option<u128>;
+
+    /// Whether `derive_key` is meaningful at all for this cipher.
+    fn can_derive_key(&self) -> bool {
+        true
+    }
+
+    /// Whether operands whose host bits are zero always produce a result
+    /// whose host bits are zero too, which CIDR operands rely on.
+    fn keeps_host_bits(&self) -> bool {
+        true
+    }
+}
+
+/// An `op` applied unit by unit, with each unit wrapping on its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitCipher {
+    op: IpOp,
+    mode: IpMode,
+}
+impl UnitCipher {
+    /// Octet-wise addition, the historical IPv4 behaviour.
+    pub const OCTET_ADD: Self = Self {
+        op: IpOp::Add,
+        mode: IpMode::Octet,
+    };
+    /// Group-wise XOR, the historical IPv6 behaviour.
+    pub const GROUP_XOR: Self = Self {
+        op: IpOp::Xor,
+        mode: IpMode::Group,
+    };
+
+    fn map_units(&self, width: u32, a: u128, b: u128, f: impl Fn(u128, u128, u32) -> u128) -> u128 {
+        let bits = self.mode.unit_bits(width);
+        let mask = unit_mask(bits);
+        let mut res = 0;
+        for shift in (0..width).step_by(bits as usize) {
+            let unit = f((a >> shift) & mask, (b >> shift) & mask, bits);
+            res |= (unit & mask) << shift;
+        }
+        res
+    }
+
+    /// Like `map_units`, but gives up as soon as one unit has no result.
+    fn try_map_units(
+        &self,
+        width: u32,
+        a: u128,
+        b: u128,
+        f: impl Fn(u128, u128, u32) -> Option<u128>,
+    ) -> Option<u128> {
+        let bits = self.mode.unit_bits(width);
+        let mask = unit_mask(bits);
+        let mut res = 0;
+        for shift in (0..width).step_by(bits as usize) {
+            let unit = f((a >> shift) & mask, (b >> shift) & mask, bits)?;
+            res |= (unit & mask) << shift;
+        }
+        Some(res)
+    }
+}
+impl AddressCipher for UnitCipher {
+    fn name(&self) -> String {
+        format!("{}-{}", self.mode.name(), self.op.name())
+    }
+
+    fn encrypt(&self, width: u32, from: u128, key: u128) -> u128 {
+        self.map_units(width, from, key, |from, key, bits| match self.op {
+            IpOp::Add => from.wrapping_add(key),
+            IpOp::Sub => from.wrapping_sub(key),
+            IpOp::Xor => from ^ key,
+            IpOp::Rotate => rotate_left(from, (key % bits as u128) as u32, bits),
+        })
+    }
+
+    fn decrypt(&self, width: u32, to: u128, key: u128) -> u128 {
+        self.map_units(width, to, key, |to, key, bits| match self.op {
+            IpOp::Add => to.wrapping_sub(key),
+            IpOp::Sub => to.wrapping_add(key),
+            IpOp::Xor => to ^ key,
+            IpOp::Rotate => rotate_left(to, bits - (key % bits as u128) as u32, bits),
+        })
+    }
+
+    fn derive_key(&self, width: u32, from: u128, to: u128) -> Option<u128> {
+        self.try_map_units(width, from, to, |from, to, bits| match self.op {
+            IpOp::Add => Some(to.wrapping_sub(from)),
+            IpOp::Sub => Some(from.wrapping_sub(to)),
+            IpOp::Xor => Some(from ^ to),
+            IpOp::Rotate => (0..bits)
+                .find(|by| rotate_left(from, *by, bits) == to)
+                .map(u128::from),
+        })
+    }
+
+    // Rotating moves network bits into the host part.
+    fn keeps_host_bits(&self) -> bool {
+        self.op != IpOp::Rotate
+    }
+}
+
+const FEISTEL_ROUNDS: u32 = 8;
+
+/// SplitMix64's output function, used to stir the Feistel round inputs.
+fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// A keyed, balanced Feistel network over the whole address. Every address
+/// maps onto another valid address of the same family, so it is format
+/// preserving, but the round function is a plain integer mixer rather than a
+/// vetted PRF: it scrambles addresses, it does not protect them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Feistel;
+impl Feistel {
+    fn round(key: u128, round: u32, half: u128, half_bits: u32) -> u128 {
+        let round_key = mix64((key >> 64) as u64 ^ mix64(key as u64 ^ round as u64));
+        mix64(round_key ^ half as u64) as u128 & unit_mask(half_bits)
+    }
+}
+impl AddressCipher for Feistel {
+    fn name(&self) -> String {
+        "feistel".to_owned()
+    }
+
+    fn encrypt(&self, width: u32, from: u128, key: u128) -> u128 {
+        let half_bits = width / 2;
+        let (mut left, mut right) = (from >> half_bits, from & unit_mask(half_bits));
+        for round in 0..FEISTEL_ROUNDS {
+            (left, right) = (right, left ^ Self::round(key, round, right, half_bits));
+        }
+        (left << half_bits) | right
+    }
+
+    fn decrypt(&self, width: u32, to: u128, key: u128) -> u128 {
+        let half_bits = width / 2;
+        let (mut left, mut right) = (to >> half_bits, to & unit_mask(half_bits));
+        for round in (0..FEISTEL_ROUNDS).rev() {
+            (left, right) = (right ^ Self::round(key, round, left, half_bits), left);
+        }
+        (left << half_bits) | right
+    }
+
+    fn derive_key(&self, _width: u32, _from: u128, _to: u128) -> Option<u128> {
+        None
+    }
+
+    fn can_derive_key(&self) -> bool {
+        false
+    }
+
+    fn keeps_host_bits(&self) -> bool {
+        false
+    }
+}
+
+pub const CIPHER_NAMES: &[&str] = &[
+    "octet-add",
+    "octet-sub",
+    "octet-xor",
+    "octet-rotate",
+    "group-add",
+    "group-sub",
+    "group-xor",
+    "group-rotate",
+    "int-add",
+    "int-sub",
+    "int-xor",
+    "int-rotate",
+    "feistel",
+];
+
+/// Looks a cipher up by the name it reports, e.g. `octet-add` or `feistel`.
+pub fn cipher_by_name(name: &str) -> Option<Box<dyn AddressCipher>> {
+    if name == "feistel" {
+        return Some(Box::new(Feistel));
+    }
+    let (mode, op) = name.split_once('-')?;
+    Some(Box::new(UnitCipher {
+        op: IpOp::parse(op).ok()?,
+        mode: IpMode::parse(mode).ok()?,
+    }))
+}
+
+/// Picks the cipher named by `cipher`, or else builds one from `op`/`mode`,
+/// falling back to the historical behaviour of the address family for
+/// whatever the caller left out.
+pub fn cipher_for_params(
+    ip_params: &IPParams,
+    addr: IpAddr,
+) -> Result<Box<dyn AddressCipher>, IpOpsError> {
+    if let Some(name) = &ip_params.cipher {
+        if ip_params.op.is_some() || ip_params.mode.is_some() {
+            return Err(IpOpsError::ConflictingOperands {
+                params: &["cipher", "op", "mode"],
+            });
+        }
+        return cipher_by_name(name).ok_or(IpOpsError::InvalidParameter {
+            param: "cipher",
+            value: name.to_owned(),
+            expected: CIPHER_NAMES,
+        });
+    }
+
+    let default = match addr {
+        IpAddr::V4(_) => UnitCipher::OCTET_ADD,
+        IpAddr::V6(_) => UnitCipher::GROUP_XOR,
+    };
+    Ok(Box::new(UnitCipher {
+        op: ip_params
+            .op
+            .as_deref()
+            .map(IpOp::parse)
+            .transpose()?
+            .unwrap_or(default.op),
+        mode: ip_params
+            .mode
+            .as_deref()
+            .map(IpMode::parse)
+            .transpose()?
+            .unwrap_or(default.mode),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_roundtrip() {
+        for name in CIPHER_NAMES {
+            assert_eq!(*name, cipher_by_name(name).unwrap().name());
+        }
+        assert!(cipher_by_name("octet-mul").is_none());
+    }
+
+    #[test]
+    fn test_decrypt_inverts_encrypt() {
+        let key = 0x2001_0db8_0000_0000_dead_beef_0000_0001;
+        for name in CIPHER_NAMES {
+            let cipher = cipher_by_name(name).unwrap();
+            for (width, from) in [(32, 0x0a00_0001), (128, 0xfe80 << 112 | 1)] {
+                let key = key & unit_mask(width);
+                let to = cipher.encrypt(width, from, key);
+                assert!(to <= unit_mask(width), "{}", name);
+                assert_eq!(from, cipher.decrypt(width, to, key), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_feistel_permutes() {
+        let to = Feistel.encrypt(32, 0x0a00_0001, 42);
+        assert_ne!(0x0a00_0001, to);
+        assert_ne!(to, Feistel.encrypt(32, 0x0a00_0001, 43));
+        assert_ne!(to, Feistel.encrypt(32, 0x0a00_0002, 42));
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    convert::Infallible,
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+    response::IntoResponse,
+    Json,
+};
+use ipnet::IpNet;
+use serde_json::json;
+
+/// Comma-separated CIDRs of the proxies allowed to report a client address.
+const TRUSTED_PROXIES_VAR: &str = "CCH_TRUSTED_PROXIES";
+/// `x-forwarded-for` (the default) or `forwarded`.
+const FORWARDED_HEADER_VAR: &str = "CCH_FORWARDED_HEADER";
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const FORWARDED: &str = "forwarded";
+
+/// Which proxies may vouch for the client address, and through which header.
+/// Only a peer inside one of the trusted networks gets its forwarding header
+/// looked at; anything else is taken to be the client itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyTrust {
+    trusted: Vec<IpNet>,
+    header: &'static str,
+}
+impl Default for ProxyTrust {
+    fn default() -> Self {
+        Self {
+            trusted: vec![],
+            header: X_FORWARDED_FOR,
+        }
+    }
+}
+impl ProxyTrust {
+    pub fn new(trusted: Vec<IpNet>, header: &str) -> Option<Self> {
+        let header = match header.to_ascii_lowercase().as_str() {
+            X_FORWARDED_FOR => X_FORWARDED_FOR,
+            FORWARDED => FORWARDED,
+            _ => return None,
+        };
+        Some(Self { trusted, header })
+    }
+
+    /// Reads the trust rules from `CCH_TRUSTED_PROXIES` and
+    /// `CCH_FORWARDED_HEADER`. Bad values are reported and leave the
+    /// defaults (trust nobody) in place.
+    pub fn shared_from_env() -> Arc<Self> {
+        let trusted = env::var(TRUSTED_PROXIES_VAR).unwrap_or_default();
+        let header = env::var(FORWARDED_HEADER_VAR).unwrap_or(X_FORWARDED_FOR.to_owned());
+        let trusted = trusted
+            .split(',')
+            .map(str::trim)
+            .filter(|net| !net.is_empty())
+            .map(|net| {
+                net.parse::<IpNet>()
+                    .or_else(|_| net.parse::<IpAddr>().map(IpNet::from))
+            })
+            .collect::<Result<Vec<_>, _>>();
+        match trusted.ok().and_then(|trusted| Self::new(trusted, &header)) {
+            Some(proxy_trust) => Arc::new(proxy_trust),
+            None => {
+                tracing::warn!(
+                    "ignoring invalid {} / {}, trusting no proxy",
+                    TRUSTED_PROXIES_VAR,
+                    FORWARDED_HEADER_VAR
+                );
+                Arc::new(Self::default())
+            }
+        }
+    }
+
+    fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.trusted.iter().any(|net| net.contains(&addr))
+    }
+
+    /// The hops recorded in the forwarding header, closest to the client
+    /// first. `None` stands for a hop that did not give a usable address.
+    fn chain(&self, headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+        let mut chain = vec![];
+        for value in headers.get_all(self.header) {
+            let Ok(value) = value.to_str() else {
+                chain.push(None);
+                continue;
+            };
+            for element in value.split(',') {
+                let node = if self.header == FORWARDED {
+                    element
+                        .split(';')
+                        .filter_map(|pair| pair.split_once('='))
+                        .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                        .map(|(_, node)| node)
+                } else {
+                    Some(element)
+                };
+                chain.push(node.and_then(parse_node));
+            }
+        }
+        chain
+    }
+
+    /// Walks back from the peer through the forwarding chain for as long as
+    /// each hop is trusted, and stops at the first one that is not.
+    pub fn resolve(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> ClientAddr {
+        let Some(peer) = peer else {
+            return ClientAddr::default();
+        };
+        let mut client_addr = ClientAddr {
+            addr: Some(peer),
+            peer: Some(peer),
+            source: "connect_info",
+            chain: vec![],
+        };
+        if !self.is_trusted(peer) {
+            return client_addr;
+        }
+
+        let chain = self.chain(headers);
+        client_addr.chain = chain.iter().flatten().copied().collect();
+        for hop in chain.into_iter().rev() {
+            let Some(hop) = hop else {
+                break;
+            };
+            client_addr.addr = Some(hop);
+            client_addr.source = self.header;
+            if !self.is_trusted(hop) {
+                break;
+            }
+        }
+        client_addr
+    }
+}
+
+/// Reads one node of a forwarding header: a bare or quoted address, with an
+/// optional port, and IPv6 addresses optionally in brackets.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?
+        .strip_suffix(']')?
+        .parse::<IpAddr>()
+        .ok()
+}
+
+/// The address a request came from, as far as the server can tell.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientAddr {
+    pub addr: Option<IpAddr>,
+    pub peer: Option<IpAddr>,
+    pub source: &'static str,
+    pub chain: Vec<IpAddr>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientAddr {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let proxy_trust = parts
+            .extensions
+            .get::<Arc<ProxyTrust>>()
+            .cloned()
+            .unwrap_or_default();
+        Ok(proxy_trust.resolve(peer, &parts.headers))
+    }
+}
+
+pub async fn whoami(client_addr: ClientAddr) -> impl IntoResponse {
+    Json(json!({
+        "client": client_addr.addr.map(|addr| addr.to_string()),
+        "peer": client_addr.peer.map(|addr| addr.to_string()),
+        "source": if client_addr.peer.is_some() { client_addr.source } else { "unknown" },
+        "chain": client_addr
+            .chain
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn trust(header: &str) -> ProxyTrust {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()];
+        ProxyTrust::new(trusted, header).unwrap()
+    }
+
+    fn headers(name: &'static str, values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn addr(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn test_untrusted_peer() {
+        let headers = headers(X_FORWARDED_FOR, &["1.2.3.4"]);
+        let client_addr = trust(X_FORWARDED_FOR).resolve(addr("203.0.113.9"), &headers);
+        assert_eq!(addr("203.0.113.9"), client_addr.addr);
+        assert_eq!("connect_info", client_addr.source);
+    }
+
+    #[test]
+    fn test_x_forwarded_for() {
+        let headers = headers(X_FORWARDED_FOR, &["6.6.6.6, 1.2.3.4", "10.1.1.1"]);
+        let client_addr = trust(X_FORWARDED_FOR).resolve(addr("10.0.0.1"), &headers);
+        assert_eq!(addr("1.2.3.4"), client_addr.addr);
+        assert_eq!(X_FORWARDED_FOR, client_addr.source);
+        assert_eq!(3, client_addr.chain.len());
+
+        let headers = self::headers(X_FORWARDED_FOR, &["garbage, 10.2.2.2"]);
+        let client_addr = trust(X_FORWARDED_FOR).resolve(addr("10.0.0.1"), &headers);
+        assert_eq!(addr("10.2.2.2"), client_addr.addr);
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let headers = headers(
+            FORWARDED,
+            &[r#"for=192.0.2.60;proto=http, For="[2001:db8:cafe::17]:4711";by=fd00::1"#],
+        );
+        let client_addr = trust(FORWARDED).resolve(addr("fd00::1"), &headers);
+        assert_eq!(addr("2001:db8:cafe::17"), client_addr.addr);
+        assert_eq!(FORWARDED, client_addr.source);
+        // The X-Forwarded-For header is not consulted when Forwarded is configured.
+        let headers = self::headers(X_FORWARDED_FOR, &["1.2.3.4"]);
+        let client_addr = trust(FORWARDED).resolve(addr("fd00::1"), &headers);
+        assert_eq!(addr("fd00::1"), client_addr.addr);
+    }
+
+    #[test]
+    fn test_no_connect_info() {
+        let client_addr = trust(X_FORWARDED_FOR).resolve(None, &HeaderMap::new());
+        assert_eq!(None, client_addr.addr);
+        assert!(ProxyTrust::new(vec![], "x-real-ip").is_none());
+    }
+}
@@ -0,0 +1,240 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use axum::{extract::Query, response::IntoResponse, Json};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{notation::Notation, IpOpsError};
+
+/// Special-purpose IPv4 blocks, most specific meaning first.
+const IPV4_SCOPES: &[(&str, &str)] = &[
+    ("unspecified", "0.0.0.0/32"),
+    ("broadcast", "255.255.255.255/32"),
+    ("this_network", "0.0.0.0/8"),
+    ("loopback", "127.0.0.0/8"),
+    ("private", "10.0.0.0/8"),
+    ("private", "172.16.0.0/12"),
+    ("private", "192.168.0.0/16"),
+    ("shared", "100.64.0.0/10"),
+    ("link_local", "169.254.0.0/16"),
+    ("documentation", "192.0.2.0/24"),
+    ("documentation", "198.51.100.0/24"),
+    ("documentation", "203.0.113.0/24"),
+    ("benchmarking", "198.18.0.0/15"),
+    ("multicast", "224.0.0.0/4"),
+    ("reserved", "240.0.0.0/4"),
+];
+
+/// Special-purpose IPv6 blocks, most specific meaning first.
+const IPV6_SCOPES: &[(&str, &str)] = &[
+    ("unspecified", "::/128"),
+    ("loopback", "::1/128"),
+    ("ipv4_mapped", "::ffff:0:0/96"),
+    ("nat64", "64:ff9b::/96"),
+    ("teredo", "2001::/32"),
+    ("benchmarking", "2001:2::/48"),
+    ("documentation", "2001:db8::/32"),
+    ("documentation", "3fff::/20"),
+    ("6to4", "2002::/16"),
+    ("unique_local", "fc00::/7"),
+    ("link_local", "fe80::/10"),
+    ("site_local", "fec0::/10"),
+    ("multicast", "ff00::/8"),
+];
+
+#[derive(Debug, Default, Deserialize)]
+pub struct InspectParams {
+    addr: Option<String>,
+    #[serde(rename = "in")]
+    input: Option<String>,
+}
+
+/// Every special-purpose block the whole of `net` falls into.
+fn scopes(net: IpNet) -> Vec<&'static str> {
+    let table = match net {
+        IpNet::V4(_) => IPV4_SCOPES,
+        IpNet::V6(_) => IPV6_SCOPES,
+    };
+    let mut scopes = vec![];
+    for (scope, block) in table {
+        let block: IpNet = block.parse().expect("scope tables hold valid prefixes");
+        if block.contains(&net) && !scopes.contains(scope) {
+            scopes.push(*scope);
+        }
+    }
+    if scopes.is_empty() {
+        scopes.push("global");
+    }
+    scopes
+}
+
+/// The IPv4 address carried inside an IPv4-mapped, NAT64, 6to4 or Teredo
+/// address, along with the Teredo server and port when there are some.
+fn embedded_ipv4(addr: Ipv6Addr) -> Option<Value> {
+    let bits = u128::from(addr);
+    let segments = addr.segments();
+    let v4 = |bits: u128| Ipv4Addr::from(bits as u32).to_string();
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, _, _] => Some(json!({ "kind": "mapped", "address": v4(bits) })),
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(json!({ "kind": "nat64", "address": v4(bits) })),
+        [0x2002, ..] => Some(json!({ "kind": "6to4", "address": v4(bits >> 80) })),
+        // Teredo obfuscates the client address and port by flipping every bit.
+        [0x2001, 0, _, _, _, port, _, _] => Some(json!({
+            "kind": "teredo",
+            "address": v4(!bits),
+            "server": v4(bits >> 64),
+            "port": !port,
+        })),
+        _ => None,
+    }
+}
+
+/// The MAC address an EUI-64 interface identifier was built from: the
+/// `ff:fe` filler in the middle is dropped and the universal/local bit is
+/// flipped back.
+pub(super) fn eui64_mac(addr: Ipv6Addr) -> Option<[u8; 6]> {
+    let octets = addr.octets();
+    if octets[11] != 0xff || octets[12] != 0xfe {
+        return None;
+    }
+    Some([
+        octets[8] ^ 0x02,
+        octets[9],
+        octets[10],
+        octets[13],
+        octets[14],
+        octets[15],
+    ])
+}
+
+pub(super) fn format_mac(mac: [u8; 6]) -> String {
+    mac.map(|octet| format!("{:02x}", octet)).join(":")
+}
+
+fn inspect(inspect_params: &InspectParams) -> Result<Value, IpOpsError> {
+    let value = inspect_params
+        .addr
+        .as_deref()
+        .ok_or(IpOpsError::MissingOperand { params: &["addr"] })?;
+    let notation = Notation::parse("in", inspect_params.input.as_deref())?;
+    let (addr, prefix_len) = notation
+        .read(value)
+        .ok_or_else(|| IpOpsError::InvalidAddress {
+            param: "addr",
+            value: value.to_owned(),
+        })?;
+    let net = match prefix_len {
+        Some(prefix_len) => IpNet::new(addr, prefix_len).map(|net| net.trunc()),
+        None => Ok(IpNet::from(addr)),
+    }
+    .map_err(|_| IpOpsError::InvalidAddress {
+        param: "addr",
+        value: value.to_owned(),
+    })?;
+
+    let scopes = scopes(net);
+    let mut report = json!({
+        "address": addr.to_string(),
+        "prefix": prefix_len.map(|_| net.to_string()),
+        "family": if addr.is_ipv4() { "ipv4" } else { "ipv6" },
+        "scope": scopes[0],
+        "scopes": scopes,
+        "global": scopes == ["global"],
+        "embedded_ipv4": Value::Null,
+        "interface_id": Value::Null,
+    });
+    if let IpAddr::V6(addr) = addr {
+        if let Some(embedded) = embedded_ipv4(addr) {
+            report["embedded_ipv4"] = embedded;
+        }
+        report["interface_id"] = json!({
+            "value": format!("{:016x}", u128::from(addr) as u64),
+            "eui64": eui64_mac(addr).is_some(),
+            "mac": eui64_mac(addr).map(format_mac),
+        });
+    }
+    Ok(report)
+}
+
+pub async fn inspect_addr(
+    inspect_params: Query<InspectParams>,
+) -> Result<impl IntoResponse, IpOpsError> {
+    Ok(Json(inspect(&inspect_params.0)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(addr: &str) -> Value {
+        inspect(&InspectParams {
+            addr: Some(addr.to_owned()),
+            input: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ipv4_scopes() {
+        assert_eq!("private", report("192.168.1.1")["scope"]);
+        assert_eq!("shared", report("100.64.1.1")["scope"]);
+        assert_eq!("documentation", report("203.0.113.7")["scope"]);
+        assert_eq!("loopback", report("127.0.0.1/8")["scope"]);
+        assert_eq!(
+            json!(["unspecified", "this_network"]),
+            report("0.0.0.0")["scopes"]
+        );
+        assert_eq!(true, report("1.1.1.1")["global"]);
+        assert_eq!("global", report("172.32.0.0/16")["scope"]);
+        // A prefix is only in a block when all of it is.
+        assert_eq!("global", report("10.0.0.0/7")["scope"]);
+        assert_eq!(Value::Null, report("10.0.0.1")["interface_id"]);
+    }
+
+    #[test]
+    fn test_ipv6_scopes() {
+        assert_eq!("link_local", report("fe80::1")["scope"]);
+        assert_eq!("unique_local", report("fd12:3456::1")["scope"]);
+        assert_eq!("multicast", report("ff02::1")["scope"]);
+        assert_eq!("2001:db8::/32", report("2001:db8::1/32")["prefix"]);
+        assert_eq!(true, report("2606:4700::1111")["global"]);
+    }
+
+    #[test]
+    fn test_embedded_ipv4() {
+        let embedded = &report("::ffff:192.0.2.1")["embedded_ipv4"];
+        assert_eq!(
+            json!({ "kind": "mapped", "address": "192.0.2.1" }),
+            *embedded
+        );
+        let embedded = &report("2002:c000:0204::1")["embedded_ipv4"];
+        assert_eq!("192.0.2.4", embedded["address"]);
+        // RFC 4380's example: server 65.54.227.120, client 192.0.2.45:40000.
+        let embedded = &report("2001:0:4136:e378:8000:63bf:3fff:fdd2")["embedded_ipv4"];
+        assert_eq!("teredo", embedded["kind"]);
+        assert_eq!("65.54.227.120", embedded["server"]);
+        assert_eq!("192.0.2.45", embedded["address"]);
+        assert_eq!(40000, embedded["port"]);
+    }
+
+    #[test]
+    fn test_eui64() {
+        let interface_id = &report("fe80::211:22ff:fe33:4455")["interface_id"];
+        assert_eq!(true, interface_id["eui64"]);
+        assert_eq!("00:11:22:33:44:55", interface_id["mac"]);
+        assert_eq!(false, report("fe80::1")["interface_id"]["eui64"]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let err = inspect(&InspectParams::default()).unwrap_err();
+        assert_eq!("missing_operand", err.code());
+        let err = inspect(&InspectParams {
+            addr: Some("fe80::/129".to_owned()),
+            input: None,
+        })
+        .unwrap_err();
+        assert_eq!("invalid_address", err.code());
+    }
+}
@@ -0,0 +1,549 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{addr_to_bits, bits_to_addr, subnet, IpOpsError};
+
+const ALLOCATED: &str = "allocated";
+const RESERVED: &str = "reserved";
+
+/// Everything that can go wrong on the `/2/ipam` routes. Errors about the
+/// state of the pools are answered with a `404 Not Found` or a `409 Conflict`,
+/// while bad parameters keep their [`IpOpsError`] and its `400 Bad Request`.
+#[derive(Debug, PartialEq)]
+pub enum IpamError {
+    PoolNotFound {
+        name: String,
+    },
+    PoolExists {
+        name: String,
+    },
+    PoolExhausted {
+        name: String,
+        prefix_len: u8,
+    },
+    AddressConflict {
+        prefix: String,
+        reason: &'static str,
+    },
+    NotAllocated {
+        prefix: String,
+    },
+    Params(IpOpsError),
+}
+impl IpamError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::PoolNotFound { .. } => "pool_not_found",
+            Self::PoolExists { .. } => "pool_exists",
+            Self::PoolExhausted { .. } => "pool_exhausted",
+            Self::AddressConflict { .. } => "address_conflict",
+            Self::NotAllocated { .. } => "not_allocated",
+            Self::Params(e) => e.code(),
+        }
+    }
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::PoolNotFound { .. } | Self::NotAllocated { .. } => StatusCode::NOT_FOUND,
+            Self::PoolExists { .. } | Self::PoolExhausted { .. } | Self::AddressConflict { .. } => {
+                StatusCode::CONFLICT
+            }
+            Self::Params(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+    fn to_json(&self) -> Value {
+        let mut body = json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        });
+        match self {
+            Self::PoolNotFound { name } | Self::PoolExists { name } => {
+                body["pool"] = json!(name);
+            }
+            Self::PoolExhausted { name, prefix_len } => {
+                body["pool"] = json!(name);
+                body["prefix_len"] = json!(prefix_len);
+            }
+            Self::AddressConflict { prefix, .. } | Self::NotAllocated { prefix } => {
+                body["prefix"] = json!(prefix);
+            }
+            Self::Params(e) => body = e.to_json(),
+        }
+        body
+    }
+}
+impl From<IpOpsError> for IpamError {
+    fn from(e: IpOpsError) -> Self {
+        Self::Params(e)
+    }
+}
+impl Display for IpamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PoolNotFound { name } => write!(f, "no pool named {:?}", name),
+            Self::PoolExists { name } => write!(f, "a pool named {:?} already exists", name),
+            Self::PoolExhausted { name, prefix_len } => {
+                write!(f, "pool {:?} has no free /{} left", name, prefix_len)
+            }
+            Self::AddressConflict { prefix, reason } => write!(f, "{} {}", prefix, reason),
+            Self::NotAllocated { prefix } => write!(f, "{} is not allocated", prefix),
+            Self::Params(e) => e.fmt(f),
+        }
+    }
+}
+impl IntoResponse for IpamError {
+    fn into_response(self) -> Response {
+        (self.status_code(), Json(self.to_json())).into_response()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Allocation {
+    net: IpNet,
+    kind: &'static str,
+}
+
+/// First and last address of `net` as integers.
+fn bounds(net: IpNet) -> (u128, u128) {
+    (addr_to_bits(net.network()), addr_to_bits(net.broadcast()))
+}
+
+/// Number of addresses in `net`, or `None` for the 2^128 of `::/0`.
+fn size(net: IpNet) -> Option<u128> {
+    1u128.checked_shl((net.max_prefix_len() - net.prefix_len()) as u32)
+}
+
+fn count_to_string(count: Option<u128>) -> String {
+    match count {
+        Some(count) => count.to_string(),
+        None => subnet::IPV6_ADDRESS_SPACE.to_owned(),
+    }
+}
+
+/// A named block of addresses handing out non-overlapping subnets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pool {
+    net: IpNet,
+    /// Keyed by the first address of each allocation.
+    allocations: BTreeMap<u128, Allocation>,
+}
+impl Pool {
+    fn new(net: IpNet) -> Self {
+        Self {
+            net: net.trunc(),
+            allocations: BTreeMap::new(),
+        }
+    }
+
+    /// The allocation overlapping `start..=end`, if any. Allocations never
+    /// overlap each other, so only the last one starting before `end` can.
+    fn overlapping(&self, start: u128, end: u128) -> Option<&Allocation> {
+        self.allocations
+            .range(..=end)
+            .next_back()
+            .map(|(_, allocation)| allocation)
+            .filter(|allocation| bounds(allocation.net).1 >= start)
+    }
+
+    /// Finds the lowest free, aligned block of `prefix_len` in the pool.
+    /// Single IPv4 hosts skip the network and broadcast addresses of pools
+    /// that have them.
+    fn next_free(&self, prefix_len: u8) -> Option<IpNet> {
+        let (mut low, mut high) = bounds(self.net);
+        let host_bits = self.net.max_prefix_len() - self.net.prefix_len();
+        if matches!(self.net, IpNet::V4(_)) && prefix_len == 32 && host_bits >= 2 {
+            low += 1;
+            high -= 1;
+        }
+        let Some(size) = 1u128.checked_shl((self.net.max_prefix_len() - prefix_len) as u32) else {
+            // Only `::/0` itself is that large.
+            return match self.allocations.is_empty() {
+                true => Some(self.net),
+                false => None,
+            };
+        };
+
+        let mut candidate = low.checked_next_multiple_of(size)?;
+        loop {
+            let end = candidate.checked_add(size - 1)?;
+            if end > high {
+                return None;
+            }
+            match self.overlapping(candidate, end) {
+                None => {
+                    let addr = bits_to_addr(self.net.addr(), candidate);
+                    return IpNet::new(addr, prefix_len).ok();
+                }
+                Some(allocation) => {
+                    candidate = bounds(allocation.net)
+                        .1
+                        .checked_add(1)?
+                        .checked_next_multiple_of(size)?;
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, net: IpNet, kind: &'static str) -> Result<(), IpamError> {
+        if !self.net.contains(&net) {
+            return Err(IpamError::AddressConflict {
+                prefix: net.to_string(),
+                reason: "is outside the pool",
+            });
+        }
+        let (start, end) = bounds(net);
+        if let Some(allocation) = self.overlapping(start, end) {
+            return Err(IpamError::AddressConflict {
+                prefix: net.to_string(),
+                reason: if allocation.kind == RESERVED {
+                    "overlaps a reservation"
+                } else {
+                    "overlaps an allocation"
+                },
+            });
+        }
+        self.allocations.insert(start, Allocation { net, kind });
+        Ok(())
+    }
+
+    fn release(&mut self, net: IpNet) -> Result<(), IpamError> {
+        let (start, _) = bounds(net);
+        match self.allocations.get(&start) {
+            Some(allocation) if allocation.net == net => {
+                self.allocations.remove(&start);
+                Ok(())
+            }
+            _ => Err(IpamError::NotAllocated {
+                prefix: net.to_string(),
+            }),
+        }
+    }
+
+    fn summary(&self, name: &str) -> Value {
+        let used = self
+            .allocations
+            .values()
+            .map(|allocation| size(allocation.net))
+            .try_fold(0u128, |used, size| used.checked_add(size?));
+        let total = size(self.net);
+        let utilisation = match (used, total) {
+            (Some(used), Some(total)) => used as f64 / total as f64 * 100.0,
+            (Some(used), None) => used as f64 / 2f64.powi(128) * 100.0,
+            (None, _) => 100.0,
+        };
+        json!({
+            "name": name,
+            "cidr": self.net.to_string(),
+            "family": if self.net.addr().is_ipv4() { "ipv4" } else { "ipv6" },
+            "total": count_to_string(total),
+            "used": count_to_string(used),
+            "allocations": self.allocations.len(),
+            "utilisation": utilisation,
+        })
+    }
+
+    fn details(&self, name: &str) -> Value {
+        let mut details = self.summary(name);
+        details["allocations"] = self
+            .allocations
+            .values()
+            .map(|allocation| json!({ "prefix": allocation.net.to_string(), "kind": allocation.kind }))
+            .collect();
+        details
+    }
+}
+
+/// Every IPAM pool, shared across requests like the connect-4 board.
+#[derive(Debug, Clone, Default)]
+pub struct IpamState {
+    pools: BTreeMap<String, Pool>,
+}
+impl IpamState {
+    pub fn rwlocked_default() -> Arc<RwLock<Self>> {
+        Arc::new(RwLock::new(Self::default()))
+    }
+
+    fn pool_mut(&mut self, name: &str) -> Result<&mut Pool, IpamError> {
+        self.pools.get_mut(name).ok_or(IpamError::PoolNotFound {
+            name: name.to_owned(),
+        })
+    }
+
+    fn create(&mut self, name: &str, net: IpNet) -> Result<&Pool, IpamError> {
+        if self.pools.contains_key(name) {
+            return Err(IpamError::PoolExists {
+                name: name.to_owned(),
+            });
+        }
+        Ok(self.pools.entry(name.to_owned()).or_insert(Pool::new(net)))
+    }
+
+    fn allocate(&mut self, name: &str, prefix_len: Option<u8>) -> Result<IpNet, IpamError> {
+        let pool = self.pool_mut(name)?;
+        let max_prefix_len = pool.net.max_prefix_len();
+        let prefix_len = prefix_len.unwrap_or(max_prefix_len);
+        if prefix_len < pool.net.prefix_len() || prefix_len > max_prefix_len {
+            return Err(IpOpsError::OutOfRange {
+                param: "prefix_len",
+                value: prefix_len.to_string(),
+                min: pool.net.prefix_len() as u128,
+                max: max_prefix_len as u128,
+            }
+            .into());
+        }
+        let net = pool.next_free(prefix_len).ok_or(IpamError::PoolExhausted {
+            name: name.to_owned(),
+            prefix_len,
+        })?;
+        pool.insert(net, ALLOCATED)?;
+        Ok(net)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct IpamParams {
+    cidr: Option<String>,
+    addr: Option<String>,
+    prefix_len: Option<String>,
+}
+
+fn required_net(param: &'static str, value: Option<&str>) -> Result<IpNet, IpOpsError> {
+    let params: &'static [&'static str] = match param {
+        "cidr" => &["cidr"],
+        _ => &["addr"],
+    };
+    let value = value.ok_or(IpOpsError::MissingOperand { params })?;
+    subnet::parse_prefix(param, value)
+}
+
+type SharedIpam = Extension<Arc<RwLock<IpamState>>>;
+
+pub async fn list_pools(Extension(state): SharedIpam) -> impl IntoResponse {
+    let ipam_state = state.read().unwrap();
+    let pools: Vec<Value> = ipam_state
+        .pools
+        .iter()
+        .map(|(name, pool)| pool.summary(name))
+        .collect();
+    Json(json!({ "pools": pools }))
+}
+
+pub async fn create_pool(
+    Path(name): Path<String>,
+    ipam_params: Query<IpamParams>,
+    Extension(state): SharedIpam,
+) -> Result<impl IntoResponse, IpamError> {
+    let net = required_net("cidr", ipam_params.cidr.as_deref())?;
+    let mut ipam_state = state.write().unwrap();
+    let pool = ipam_state.create(&name, net)?;
+    Ok((StatusCode::CREATED, Json(pool.summary(&name))))
+}
+
+pub async fn show_pool(
+    Path(name): Path<String>,
+    Extension(state): SharedIpam,
+) -> Result<impl IntoResponse, IpamError> {
+    let ipam_state = state.read().unwrap();
+    let pool = ipam_state
+        .pools
+        .get(&name)
+        .ok_or(IpamError::PoolNotFound { name: name.clone() })?;
+    Ok(Json(pool.details(&name)))
+}
+
+pub async fn delete_pool(
+    Path(name): Path<String>,
+    Extension(state): SharedIpam,
+) -> Result<impl IntoResponse, IpamError> {
+    let mut ipam_state = state.write().unwrap();
+    ipam_state
+        .pools
+        .remove(&name)
+        .ok_or(IpamError::PoolNotFound { name })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Hands out the next free address, or the next free subnet of `prefix_len`.
+pub async fn allocate(
+    Path(name): Path<String>,
+    ipam_params: Query<IpamParams>,
+    Extension(state): SharedIpam,
+) -> Result<impl IntoResponse, IpamError> {
+    let prefix_len = ipam_params
+        .prefix_len
+        .as_deref()
+        .map(|prefix_len| {
+            prefix_len
+                .parse::<u8>()
+                .map_err(|_| IpOpsError::OutOfRange {
+                    param: "prefix_len",
+                    value: prefix_len.to_owned(),
+                    min: 0,
+                    max: 128,
+                })
+        })
+        .transpose()?;
+    let mut ipam_state = state.write().unwrap();
+    let net = ipam_state.allocate(&name, prefix_len)?;
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "pool": name, "prefix": net.to_string(), "kind": ALLOCATED })),
+    ))
+}
+
+pub async fn reserve(
+    Path(name): Path<String>,
+    ipam_params: Query<IpamParams>,
+    Extension(state): SharedIpam,
+) -> Result<impl IntoResponse, IpamError> {
+    let net = required_net("addr", ipam_params.addr.as_deref())?;
+    let mut ipam_state = state.write().unwrap();
+    ipam_state.pool_mut(&name)?.insert(net, RESERVED)?;
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "pool": name, "prefix": net.to_string(), "kind": RESERVED })),
+    ))
+}
+
+pub async fn release(
+    Path(name): Path<String>,
+    ipam_params: Query<IpamParams>,
+    Extension(state): SharedIpam,
+) -> Result<impl IntoResponse, IpamError> {
+    let net = required_net("addr", ipam_params.addr.as_deref())?;
+    let mut ipam_state = state.write().unwrap();
+    ipam_state.pool_mut(&name)?.release(net)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(net: &str) -> IpNet {
+        net.parse().unwrap()
+    }
+
+    #[test]
+    fn test_allocate_hosts() {
+        let mut ipam_state = IpamState::default();
+        ipam_state.create("lab", net("10.0.0.0/30")).unwrap();
+        assert_eq!(
+            net("10.0.0.1/32"),
+            ipam_state.allocate("lab", None).unwrap()
+        );
+        assert_eq!(
+            net("10.0.0.2/32"),
+            ipam_state.allocate("lab", None).unwrap()
+        );
+        assert_eq!(
+            "pool_exhausted",
+            ipam_state.allocate("lab", None).unwrap_err().code()
+        );
+        let pool = ipam_state.pool_mut("lab").unwrap();
+        pool.release(net("10.0.0.1/32")).unwrap();
+        assert_eq!(
+            "not_allocated",
+            pool.release(net("10.0.0.1/32")).unwrap_err().code()
+        );
+        assert_eq!(
+            net("10.0.0.1/32"),
+            ipam_state.allocate("lab", None).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_allocate_subnets() {
+        let mut ipam_state = IpamState::default();
+        ipam_state.create("v6", net("2001:db8::/48")).unwrap();
+        let pool = ipam_state.pool_mut("v6").unwrap();
+        pool.insert(net("2001:db8::5/128"), RESERVED).unwrap();
+        pool.insert(net("2001:db8:0:1::/64"), RESERVED).unwrap();
+        assert_eq!(
+            net("2001:db8:0:2::/64"),
+            ipam_state.allocate("v6", Some(64)).unwrap()
+        );
+        assert_eq!(
+            net("2001:db8::/128"),
+            ipam_state.allocate("v6", None).unwrap()
+        );
+        assert_eq!(
+            net("2001:db8::2/127"),
+            ipam_state.allocate("v6", Some(127)).unwrap()
+        );
+        assert_eq!(
+            net("2001:db8::6/127"),
+            ipam_state.allocate("v6", Some(127)).unwrap()
+        );
+        assert_eq!(
+            "out_of_range",
+            ipam_state.allocate("v6", Some(47)).unwrap_err().code()
+        );
+        assert_eq!(
+            "pool_not_found",
+            ipam_state.allocate("v4", None).unwrap_err().code()
+        );
+    }
+
+    #[test]
+    fn test_reserve_conflicts() {
+        let mut pool = Pool::new(net("192.168.0.0/24"));
+        pool.insert(net("192.168.0.128/25"), RESERVED).unwrap();
+        let err = pool.insert(net("192.168.0.200/32"), ALLOCATED).unwrap_err();
+        assert_eq!(StatusCode::CONFLICT, err.status_code());
+        let err = pool.insert(net("192.168.1.1/32"), ALLOCATED).unwrap_err();
+        assert_eq!("address_conflict", err.code());
+        pool.insert(net("192.168.0.0/25"), ALLOCATED).unwrap();
+        assert_eq!(None, pool.next_free(32));
+    }
+
+    #[test]
+    fn test_required_net() {
+        assert_eq!(
+            Ok(net("10.0.0.0/24")),
+            required_net("addr", Some("10.0.0.5/24"))
+        );
+        assert_eq!(
+            Ok(net("10.0.0.5/32")),
+            required_net("addr", Some("10.0.0.5"))
+        );
+        assert_eq!(
+            "missing_operand",
+            required_net("cidr", None).unwrap_err().code()
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut ipam_state = IpamState::default();
+        ipam_state.create("lab", net("10.1.0.0/24")).unwrap();
+        assert_eq!(
+            "pool_exists",
+            ipam_state
+                .create("lab", net("10.2.0.0/24"))
+                .unwrap_err()
+                .code()
+        );
+        ipam_state.allocate("lab", Some(26)).unwrap();
+        let summary = ipam_state.pools["lab"].summary("lab");
+        assert_eq!("256", summary["total"]);
+        assert_eq!("64", summary["used"]);
+        assert_eq!(25.0, summary["utilisation"]);
+
+        ipam_state.create("all", net("::/0")).unwrap();
+        assert_eq!(net("::/0"), ipam_state.allocate("all", Some(0)).unwrap());
+        assert_eq!(100.0, ipam_state.pools["all"].summary("all")["utilisation"]);
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Display,
+    fs,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{client::ClientAddr, IpOpsError, MAX_BATCH_JOBS};
+
+/// Path of the range file; lookups answer `503` without one.
+const DATABASE_VAR: &str = "CCH_LOOKUP_DB";
+/// Seconds between checks of the file's modification time.
+const RELOAD_INTERVAL_VAR: &str = "CCH_LOOKUP_RELOAD_SECS";
+const DEFAULT_RELOAD_INTERVAL: u64 = 10;
+
+/// Everything that can go wrong on the `/2/lookup` routes. A server without
+/// a usable database answers `503 Service Unavailable`; the rest are the
+/// client's mistakes.
+#[derive(Debug, PartialEq)]
+pub enum LookupError {
+    Unavailable { reason: String },
+    InvalidBatch { message: String },
+    Params(IpOpsError),
+}
+impl LookupError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unavailable { .. } => "lookup_unavailable",
+            Self::InvalidBatch { .. } => "invalid_batch",
+            Self::Params(e) => e.code(),
+        }
+    }
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Params(e) => e.to_json(),
+            _ => json!({
+                "error": self.code(),
+                "message": self.to_string(),
+            }),
+        }
+    }
+}
+impl From<IpOpsError> for LookupError {
+    fn from(e: IpOpsError) -> Self {
+        Self::Params(e)
+    }
+}
+impl Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unavailable { reason } => {
+                write!(f, "no lookup database is loaded: {}", reason)
+            }
+            Self::InvalidBatch { message } => write!(f, "malformed batch: {}", message),
+            Self::Params(e) => e.fmt(f),
+        }
+    }
+}
+impl IntoResponse for LookupError {
+    fn into_response(self) -> Response {
+        (self.status_code(), Json(self.to_json())).into_response()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Network {
+    end: IpAddr,
+    asn: u32,
+    org: String,
+    country: String,
+}
+
+/// Address ranges and who they belong to, keyed on their first address.
+/// Ranges never overlap, so the one holding an address is the last one
+/// starting at or before it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LookupDb {
+    networks: BTreeMap<IpAddr, Network>,
+}
+impl LookupDb {
+    fn find(&self, addr: IpAddr) -> Option<(IpAddr, &Network)> {
+        self.networks
+            .range(..=addr)
+            .next_back()
+            .filter(|(_, network)| addr <= network.end)
+            .map(|(start, network)| (*start, network))
+    }
+
+    fn lookup(&self, addr: IpAddr) -> Value {
+        match self.find(addr) {
+            Some((start, network)) => json!({
+                "addr": addr.to_string(),
+                "found": true,
+                "range": { "start": start.to_string(), "end": network.end.to_string() },
+                "asn": network.asn,
+                "org": network.org,
+                "country": network.country,
+            }),
+            None => json!({ "addr": addr.to_string(), "found": false }),
+        }
+    }
+}
+
+/// Reads a range file, one network per line, fields separated by tabs or
+/// commas (the organisation, last, may contain commas of its own):
+///
+/// ```text
+/// start,end,asn,country,org
+/// cidr,asn,country,org
+/// ```
+///
+/// Blank lines and `#` comments are skipped, as is the first remaining line
+/// if it is not a range, taken as a header. MMDB files are not supported.
+impl FromStr for LookupDb {
+    type Err = String;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let mut networks: BTreeMap<IpAddr, Network> = BTreeMap::new();
+        let mut first_line = true;
+        for (number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let header_allowed = std::mem::replace(&mut first_line, false);
+            let delimiter = if line.contains('\t') { '\t' } else { ',' };
+            let first = line.split(delimiter).next().unwrap_or_default().trim();
+            let (start, end, rest) = if let Ok(net) = first.parse::<IpNet>() {
+                let rest = line.split_once(delimiter).map_or("", |(_, rest)| rest);
+                (net.network(), net.broadcast(), rest)
+            } else if let Ok(start) = first.parse::<IpAddr>() {
+                let mut fields = line.splitn(3, delimiter).skip(1);
+                let end = fields
+                    .next()
+                    .and_then(|end| end.trim().parse::<IpAddr>().ok())
+                    .filter(|end| end.is_ipv4() == start.is_ipv4() && start <= *end)
+                    .ok_or(format!("line {}: invalid range end", number + 1))?;
+                (start, end, fields.next().unwrap_or_default())
+            } else if header_allowed {
+                continue;
+            } else {
+                return Err(format!(
+                    "line {}: invalid range start {:?}",
+                    number + 1,
+                    first
+                ));
+            };
+
+            let mut fields = rest
+                .splitn(3, delimiter)
+                .map(|field| field.trim().trim_matches('"'));
+            let asn = fields.next().unwrap_or_default();
+            let asn = asn
+                .strip_prefix("AS")
+                .or(asn.strip_prefix("as"))
+                .unwrap_or(asn)
+                .parse::<u32>()
+                .map_err(|_| format!("line {}: invalid ASN {:?}", number + 1, asn))?;
+            let country = fields.next().unwrap_or_default().to_owned();
+            let org = fields.next().unwrap_or_default().to_owned();
+
+            let overlaps = networks
+                .range(..=end)
+                .next_back()
+                .is_some_and(|(_, network)| start <= network.end);
+            if overlaps {
+                return Err(format!(
+                    "line {}: {}-{} overlaps an earlier range",
+                    number + 1,
+                    start,
+                    end
+                ));
+            }
+            networks.insert(
+                start,
+                Network {
+                    end,
+                    asn,
+                    org,
+                    country,
+                },
+            );
+        }
+        Ok(Self { networks })
+    }
+}
+
+/// The lookup database, reloaded whenever its file changes.
+#[derive(Debug, Default)]
+pub struct LookupState {
+    path: Option<PathBuf>,
+    db: RwLock<Option<Arc<LookupDb>>>,
+    modified: RwLock<Option<SystemTime>>,
+    /// The last reload failure, so that each one is logged once.
+    failure: RwLock<Option<String>>,
+}
+impl LookupState {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            ..Self::default()
+        }
+    }
+
+    /// Loads the file named by `CCH_LOOKUP_DB` and keeps it fresh by polling
+    /// its modification time every `CCH_LOOKUP_RELOAD_SECS` seconds.
+    pub fn shared_from_env() -> Arc<Self> {
+        let lookup_state = Arc::new(Self::new(env::var_os(DATABASE_VAR).map(PathBuf::from)));
+        if lookup_state.path.is_none() {
+            return lookup_state;
+        }
+        lookup_state.reload_logged();
+        let interval = env::var(RELOAD_INTERVAL_VAR)
+            .ok()
+            .and_then(|secs| secs.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_RELOAD_INTERVAL);
+        let watched = lookup_state.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(interval.max(1))).await;
+                watched.reload_logged();
+            }
+        });
+        lookup_state
+    }
+
+    /// Re-reads the file if it changed since the last load, and tells whether
+    /// it did. A file that fails to parse leaves the current data in place.
+    pub fn reload(&self) -> Result<bool, String> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if *self.modified.read().unwrap() == Some(modified) {
+            return Ok(false);
+        }
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let db = data.parse::<LookupDb>()?;
+        *self.db.write().unwrap() = Some(Arc::new(db));
+        *self.modified.write().unwrap() = Some(modified);
+        Ok(true)
+    }
+
+    /// Reloads, logging a failure only when it differs from the last one.
+    fn reload_logged(&self) {
+        let failure = self.reload().err();
+        if let Some(e) = &failure {
+            if self.failure.read().unwrap().as_ref() != Some(e) {
+                tracing::warn!(error = %e, "keeping the previous lookup database");
+            }
+        }
+        *self.failure.write().unwrap() = failure;
+    }
+
+    fn db(&self) -> Result<Arc<LookupDb>, LookupError> {
+        self.db
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| LookupError::Unavailable {
+                reason: match &self.path {
+                    Some(path) => format!("{} could not be loaded", path.display()),
+                    None => format!("{} is not set", DATABASE_VAR),
+                },
+            })
+    }
+}
+
+fn parse_addr(value: &str) -> Result<IpAddr, IpOpsError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| IpOpsError::InvalidAddress {
+            param: "addr",
+            value: value.to_owned(),
+        })
+}
+
+fn lookup_batch(db: &LookupDb, addrs: &[String]) -> Result<Vec<Value>, LookupError> {
+    if addrs.len() > MAX_BATCH_JOBS {
+        return Err(LookupError::InvalidBatch {
+            message: format!("at most {} addresses are allowed", MAX_BATCH_JOBS),
+        });
+    }
+    Ok(addrs
+        .iter()
+        .enumerate()
+        .map(|(index, addr)| match parse_addr(addr) {
+            Ok(addr) => json!({ "index": index, "result": db.lookup(addr) }),
+            Err(e) => json!({ "index": index, "error": e.to_json() }),
+        })
+        .collect())
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LookupParams {
+    addr: Option<String>,
+}
+
+/// Resolves `addr`, or the caller's own address, to its ASN and country.
+pub async fn lookup_addr(
+    client_addr: ClientAddr,
+    lookup_params: Query<LookupParams>,
+    Extension(lookup_state): Extension<Arc<LookupState>>,
+) -> Result<impl IntoResponse, LookupError> {
+    let db = lookup_state.db()?;
+    let addr = match lookup_params.addr.as_deref() {
+        Some(addr) => parse_addr(addr)?,
+        None => client_addr
+            .addr
+            .ok_or(IpOpsError::MissingOperand { params: &["addr"] })?,
+    };
+    Ok(Json(db.lookup(addr)))
+}
+
+/// Resolves a JSON array of addresses; a bad one only fails its own entry.
+pub async fn lookup_addrs(
+    Extension(lookup_state): Extension<Arc<LookupState>>,
+    Json(addrs): Json<Vec<String>>,
+) -> Result<impl IntoResponse, LookupError> {
+    let db = lookup_state.db()?;
+    Ok(Json(lookup_batch(&db, &addrs)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = "\
+range_start,range_end,asn,country,org
+# Cloudflare
+1.0.0.0,1.0.0.255,AS13335,US,\"Cloudflare, Inc.\"
+8.8.8.0/24\t15169\tUS\tGOOGLE
+2001:db8::,2001:db8::ffff,64496,ZZ,Documentation
+";
+
+    fn addr(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let db: LookupDb = DATA.parse().unwrap();
+        let found = db.lookup(addr("1.0.0.1"));
+        assert_eq!(true, found["found"]);
+        assert_eq!(13335, found["asn"]);
+        assert_eq!("Cloudflare, Inc.", found["org"]);
+        assert_eq!("1.0.0.255", found["range"]["end"]);
+        assert_eq!("GOOGLE", db.lookup(addr("8.8.8.8"))["org"]);
+        assert_eq!("ZZ", db.lookup(addr("2001:db8::abcd"))["country"]);
+        assert_eq!(false, db.lookup(addr("1.0.1.0"))["found"]);
+        assert_eq!(false, db.lookup(addr("2001:db8::1:0"))["found"]);
+        assert_eq!(false, db.lookup(addr("::1"))["found"]);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let err = "1.0.0.0,1.0.0.255,13335,US,x\n1.0.0.128/25,1,US,y"
+            .parse::<LookupDb>()
+            .unwrap_err();
+        assert!(err.starts_with("line 2:"));
+        assert!("1.0.0.9,1.0.0.1,1,US,x".parse::<LookupDb>().is_err());
+        assert!("1.0.0.0,::1,1,US,x".parse::<LookupDb>().is_err());
+        assert!("1.0.0.0/8,ASx,US,x".parse::<LookupDb>().is_err());
+        assert!("1.0.0.0/8,1,US,x\nnonsense,1,US,x"
+            .parse::<LookupDb>()
+            .is_err());
+        // Only one header line is skipped before the first range.
+        let err = "# ranges\nstart,end,asn,country,org\ncorrupt,1,US,x\n1.0.0.0/8,1,US,x"
+            .parse::<LookupDb>()
+            .unwrap_err();
+        assert!(err.starts_with("line 3:"));
+    }
+
+    #[test]
+    fn test_batch() {
+        let db: LookupDb = DATA.parse().unwrap();
+        let results = lookup_batch(&db, &["8.8.4.4".to_owned(), "bogus".to_owned()]).unwrap();
+        assert_eq!(false, results[0]["result"]["found"]);
+        assert_eq!("invalid_address", results[1]["error"]["error"]);
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = env::temp_dir().join(format!("cch-lookup-{}.csv", std::process::id()));
+        let lookup_state = LookupState::new(Some(path.clone()));
+        assert!(lookup_state.reload().is_err());
+        assert_eq!("lookup_unavailable", lookup_state.db().unwrap_err().code());
+
+        fs::write(&path, DATA).unwrap();
+        assert_eq!(Ok(true), lookup_state.reload());
+        assert_eq!(Ok(false), lookup_state.reload());
+        // A broken update is refused and the previous data kept.
+        fs::write(&path, "1.0.0.0/8,not an asn,US,x").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(lookup_state.reload().is_err());
+        assert_eq!(3, lookup_state.db().unwrap().networks.len());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,917 @@
+use core::str;
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use client::ClientAddr;
+use notation::Notation;
+
+pub mod acl;
+pub mod cipher;
+pub mod client;
+pub mod inspect;
+pub mod ipam;
+pub mod lookup;
+pub mod notation;
+pub mod range;
+pub mod recover;
+pub mod slaac;
+pub mod subnet;
+
+const NDJSON_MIME_TYPES: &[&str] = &["application/x-ndjson", "application/ndjson"];
+const MAX_BATCH_JOBS: usize = 10_000;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct IPParams {
+    from: Option<String>,
+    key: Option<String>,
+    to: Option<String>,
+    op: Option<String>,
+    mode: Option<String>,
+    cipher: Option<String>,
+    #[serde(rename = "in")]
+    input: Option<String>,
+    #[serde(rename = "out")]
+    output: Option<String>,
+}
+
+/// Everything a client can get wrong when calling the `/2/*` address routes.
+/// Each variant is rendered as a `400 Bad Request` with a JSON body whose
+/// `error` field is a stable, machine-readable code.
+#[derive(Debug, PartialEq)]
+pub enum IpOpsError {
+    InvalidAddress {
+        param: &'static str,
+        value: String,
+    },
+    FamilyMismatch {
+        base: &'static str,
+        base_addr: IpAddr,
+        param: &'static str,
+        param_addr: IpAddr,
+    },
+    MissingOperand {
+        params: &'static [&'static str],
+    },
+    ConflictingOperands {
+        params: &'static [&'static str],
+    },
+    PrefixMismatch {
+        base: &'static str,
+        base_prefix: u8,
+        param: &'static str,
+        param_prefix: u8,
+    },
+    InvalidParameter {
+        param: &'static str,
+        value: String,
+        expected: &'static [&'static str],
+    },
+    KeyNotFound {
+        from: IpAddr,
+        to: IpAddr,
+    },
+    OutOfRange {
+        param: &'static str,
+        value: String,
+        min: u128,
+        max: u128,
+    },
+    UnsupportedOperation {
+        cipher: String,
+        operation: &'static str,
+    },
+    Unrepresentable {
+        notation: &'static str,
+        value: String,
+    },
+}
+impl IpOpsError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidAddress { .. } => "invalid_address",
+            Self::FamilyMismatch { .. } => "family_mismatch",
+            Self::MissingOperand { .. } => "missing_operand",
+            Self::ConflictingOperands { .. } => "conflicting_operands",
+            Self::PrefixMismatch { .. } => "prefix_mismatch",
+            Self::InvalidParameter { .. } => "invalid_parameter",
+            Self::KeyNotFound { .. } => "key_not_found",
+            Self::OutOfRange { .. } => "out_of_range",
+            Self::UnsupportedOperation { .. } => "unsupported_operation",
+            Self::Unrepresentable { .. } => "unrepresentable",
+        }
+    }
+    fn to_json(&self) -> Value {
+        let mut body = json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        });
+        match self {
+            Self::InvalidAddress { param, value } => {
+                body["param"] = json!(param);
+                body["value"] = json!(value);
+            }
+            Self::FamilyMismatch {
+                base,
+                base_addr,
+                param,
+                param_addr,
+            } => {
+                body["base"] = json!(base);
+                body["base_addr"] = json!(base_addr.to_string());
+                body["param"] = json!(param);
+                body["param_addr"] = json!(param_addr.to_string());
+            }
+            Self::MissingOperand { params } | Self::ConflictingOperands { params } => {
+                body["params"] = json!(params);
+            }
+            Self::PrefixMismatch {
+                base,
+                base_prefix,
+                param,
+                param_prefix,
+            } => {
+                body["base"] = json!(base);
+                body["base_prefix"] = json!(base_prefix);
+                body["param"] = json!(param);
+                body["param_prefix"] = json!(param_prefix);
+            }
+            Self::InvalidParameter {
+                param,
+                value,
+                expected,
+            } => {
+                body["param"] = json!(param);
+                body["value"] = json!(value);
+                body["expected"] = json!(expected);
+            }
+            Self::KeyNotFound { from, to } => {
+                body["from"] = json!(from.to_string());
+                body["to"] = json!(to.to_string());
+            }
+            Self::OutOfRange {
+                param,
+                value,
+                min,
+                max,
+            } => {
+                body["param"] = json!(param);
+                body["value"] = json!(value);
+                body["min"] = json!(min.to_string());
+                body["max"] = json!(max.to_string());
+            }
+            Self::UnsupportedOperation { cipher, operation } => {
+                body["cipher"] = json!(cipher);
+                body["operation"] = json!(operation);
+            }
+            Self::Unrepresentable { notation, value } => {
+                body["notation"] = json!(notation);
+                body["value"] = json!(value);
+            }
+        }
+        body
+    }
+}
+impl Display for IpOpsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidAddress { param, value } => {
+                write!(f, "`{}` is not a valid IP address: {:?}", param, value)
+            }
+            Self::FamilyMismatch {
+                base,
+                base_addr,
+                param,
+                param_addr,
+            } => write!(
+                f,
+                "`{}` ({}) and `{}` ({}) are not of the same address family",
+                base, base_addr, param, param_addr
+            ),
+            Self::MissingOperand { params } => {
+                write!(f, "missing operand, expected one of: {}", params.join(", "))
+            }
+            Self::ConflictingOperands { params } => {
+                write!(f, "only one of {} may be given", params.join(", "))
+            }
+            Self::PrefixMismatch {
+                base,
+                base_prefix,
+                param,
+                param_prefix,
+            } => write!(
+                f,
+                "`{}` (/{}) and `{}` (/{}) have different prefix lengths",
+                base, base_prefix, param, param_prefix
+            ),
+            Self::InvalidParameter {
+                param,
+                value,
+                expected,
+            } => write!(
+                f,
+                "`{}` must be one of {}, got {:?}",
+                param,
+                expected.join(", "),
+                value
+            ),
+            Self::KeyNotFound { from, to } => {
+                write!(f, "no key maps `from` ({}) onto `to` ({})", from, to)
+            }
+            Self::OutOfRange {
+                param,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "`{}` must be between {} and {}, got {:?}",
+                param, min, max, value
+            ),
+            Self::UnsupportedOperation { cipher, operation } => {
+                write!(f, "cipher `{}` does not support {}", cipher, operation)
+            }
+            Self::Unrepresentable { notation, value } => {
+                write!(f, "{} cannot be written in `{}` notation", value, notation)
+            }
+        }
+    }
+}
+impl IntoResponse for IpOpsError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, Json(self.to_json())).into_response()
+    }
+}
+
+/// Everything that can go wrong with a `/2/batch` body, either as a whole or
+/// for a single row. A row that parses but fails in [`calc_ip`] keeps the
+/// error it would get on its own.
+#[derive(Debug, PartialEq)]
+pub enum BatchError {
+    InvalidJob { message: String },
+    InvalidBatch { message: String },
+    Job(IpOpsError),
+}
+impl BatchError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidJob { .. } => "invalid_job",
+            Self::InvalidBatch { .. } => "invalid_batch",
+            Self::Job(e) => e.code(),
+        }
+    }
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Job(e) => e.to_json(),
+            _ => json!({
+                "error": self.code(),
+                "message": self.to_string(),
+            }),
+        }
+    }
+}
+impl From<IpOpsError> for BatchError {
+    fn from(e: IpOpsError) -> Self {
+        Self::Job(e)
+    }
+}
+impl Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidJob { message } => write!(f, "malformed job: {}", message),
+            Self::InvalidBatch { message } => write!(f, "malformed batch: {}", message),
+            Self::Job(e) => e.fmt(f),
+        }
+    }
+}
+impl IntoResponse for BatchError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, Json(self.to_json())).into_response()
+    }
+}
+
+const OPERANDS: &[&str] = &["key", "to"];
+
+/// What a request asks for, decided by which of `from`, `key` and `to` it has.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    /// `from` + `key`: where does `from` go?
+    Encrypt,
+    /// `from` + `to`: which key sends `from` to `to`?
+    DeriveKey,
+    /// `to` + `key`: where did `to` come from?
+    Decrypt,
+}
+
+/// An address operand, optionally written in CIDR notation (`10.0.0.0/24`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct IpOperand {
+    addr: IpAddr,
+    prefix_len: Option<u8>,
+}
+
+fn parse_addr(
+    notation: Notation,
+    param: &'static str,
+    value: &str,
+) -> Result<IpOperand, IpOpsError> {
+    let (addr, prefix_len) = notation
+        .read(value)
+        .ok_or_else(|| IpOpsError::InvalidAddress {
+            param,
+            value: value.to_owned(),
+        })?;
+    Ok(IpOperand { addr, prefix_len })
+}
+
+fn addr_to_bits(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(addr) => u32::from(addr) as u128,
+        IpAddr::V6(addr) => u128::from(addr),
+    }
+}
+
+/// Builds an address of the same family as `like` from its integer value.
+fn bits_to_addr(like: IpAddr, bits: u128) -> IpAddr {
+    match like {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(bits as u32)),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(bits)),
+    }
+}
+
+fn calc_ip(ip_params: &IPParams) -> Result<String, IpOpsError> {
+    calc_ip_for(ip_params, None)
+}
+
+/// Like `calc_ip`, with `client` standing in for a missing `from` unless the
+/// request is asking to decrypt. It is used as is, whatever the `in` notation.
+fn calc_ip_for(ip_params: &IPParams, client: Option<IpAddr>) -> Result<String, IpOpsError> {
+    let decrypting = ip_params.key.is_some() && ip_params.to.is_some();
+    let client = client.filter(|_| ip_params.from.is_none() && !decrypting);
+    let from = ip_params.from.as_deref().or(client.map(|_| ""));
+    let (direction, (base, base_value), (param, other)) =
+        match (from, ip_params.key.as_deref(), ip_params.to.as_deref()) {
+            (Some(from), Some(key), None) => (Direction::Encrypt, ("from", from), ("key", key)),
+            (Some(from), None, Some(to)) => (Direction::DeriveKey, ("from", from), ("to", to)),
+            (None, Some(key), Some(to)) => (Direction::Decrypt, ("to", to), ("key", key)),
+            (Some(_), Some(_), Some(_)) => {
+                return Err(IpOpsError::ConflictingOperands { params: OPERANDS })
+            }
+            (Some(_), None, None) => return Err(IpOpsError::MissingOperand { params: OPERANDS }),
+            (None, _, _) => return Err(IpOpsError::MissingOperand { params: &["from"] }),
+        };
+    let input = Notation::parse("in", ip_params.input.as_deref())?;
+    let output = Notation::parse("out", ip_params.output.as_deref())?;
+    let base_addr = match client {
+        Some(addr) => IpOperand {
+            addr,
+            prefix_len: None,
+        },
+        None => parse_addr(input, base, base_value)?,
+    };
+    let other_addr = parse_addr(input, param, other)?;
+    if base_addr.addr.is_ipv4() != other_addr.addr.is_ipv4() {
+        return Err(IpOpsError::FamilyMismatch {
+            base,
+            base_addr: base_addr.addr,
+            param,
+            param_addr: other_addr.addr,
+        });
+    }
+    let cipher = cipher::cipher_for_params(ip_params, base_addr.addr)?;
+    if direction == Direction::DeriveKey && !cipher.can_derive_key() {
+        return Err(IpOpsError::UnsupportedOperation {
+            cipher: cipher.name(),
+            operation: "key derivation",
+        });
+    }
+
+    let prefix_len = match (base_addr.prefix_len, other_addr.prefix_len) {
+        (Some(from), Some(other)) if from != other => {
+            return Err(IpOpsError::PrefixMismatch {
+                base,
+                base_prefix: from,
+                param,
+                param_prefix: other,
+            })
+        }
+        (from, other) => from.or(other),
+    };
+    // A prefix transforms the whole network: only the network bits go through
+    // the cipher, while the host bits of the base address are carried over
+    // unchanged, so every address of its network maps onto the same host of
+    // the result. Ciphers that spread network bits into the host part cannot
+    // honour that and are refused.
+    let net = match prefix_len {
+        // `parse_addr` checked each prefix against its own address, and both
+        // addresses are of the same family, so either prefix fits the base.
+        Some(prefix_len) => IpNet::new(base_addr.addr, prefix_len)
+            .expect("operand prefixes are validated for their family"),
+        None => IpNet::from(base_addr.addr),
+    };
+    if prefix_len.is_some() && !cipher.keeps_host_bits() {
+        return Err(IpOpsError::UnsupportedOperation {
+            cipher: cipher.name(),
+            operation: "CIDR prefixes",
+        });
+    }
+    let width = net.max_prefix_len() as u32;
+    let mask = addr_to_bits(net.netmask());
+    let base_bits = addr_to_bits(base_addr.addr);
+    let other_bits = addr_to_bits(other_addr.addr);
+
+    let res_bits = match direction {
+        Direction::Encrypt => {
+            let res_bits = cipher.encrypt(width, base_bits & mask, other_bits & mask);
+            (res_bits & mask) | (base_bits & !mask)
+        }
+        Direction::Decrypt => {
+            let res_bits = cipher.decrypt(width, base_bits & mask, other_bits & mask);
+            (res_bits & mask) | (base_bits & !mask)
+        }
+        Direction::DeriveKey => {
+            let res_bits = cipher
+                .derive_key(width, base_bits & mask, other_bits & mask)
+                .ok_or(IpOpsError::KeyNotFound {
+                    from: base_addr.addr,
+                    to: other_addr.addr,
+                })?;
+            res_bits & mask
+        }
+    };
+    output.write(bits_to_addr(base_addr.addr, res_bits), prefix_len)
+}
+
+/// Like `calc_ip`, but a request without `from` (that is not asking to
+/// decrypt) is about the caller's own address.
+pub async fn calc_ip_ops(
+    client_addr: ClientAddr,
+    ip_params: Query<IPParams>,
+) -> Result<impl IntoResponse, IpOpsError> {
+    let res = calc_ip_for(&ip_params, client_addr.addr)?;
+    Ok(([(header::CONTENT_TYPE, "text/plain")], res))
+}
+
+fn run_job(job: Result<Value, BatchError>) -> Result<String, BatchError> {
+    let ip_params: IPParams = serde_json::from_value(job?).map_err(|e| BatchError::InvalidJob {
+        message: e.to_string(),
+    })?;
+    Ok(calc_ip(&ip_params)?)
+}
+
+/// Runs every job of a batch on its own, so that a bad row only produces an
+/// error entry at its index instead of failing the whole request.
+fn run_batch(ndjson: bool, data: &[u8]) -> Result<Vec<Value>, BatchError> {
+    let utf8_str = str::from_utf8(data).map_err(|e| BatchError::InvalidBatch {
+        message: e.to_string(),
+    })?;
+    let jobs: Vec<Result<Value, BatchError>> = if ndjson {
+        utf8_str
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line).map_err(|e| BatchError::InvalidJob {
+                    message: e.to_string(),
+                })
+            })
+            .collect()
+    } else {
+        serde_json::from_str::<Vec<Value>>(utf8_str)
+            .map_err(|e| BatchError::InvalidBatch {
+                message: e.to_string(),
+            })?
+            .into_iter()
+            .map(Ok)
+            .collect()
+    };
+    if jobs.len() > MAX_BATCH_JOBS {
+        return Err(BatchError::InvalidBatch {
+            message: format!("at most {} jobs are allowed", MAX_BATCH_JOBS),
+        });
+    }
+
+    Ok(jobs
+        .into_iter()
+        .enumerate()
+        .map(|(index, job)| match run_job(job) {
+            Ok(res) => json!({ "index": index, "result": res }),
+            Err(e) => json!({ "index": index, "error": e.to_json() }),
+        })
+        .collect())
+}
+
+/// Takes a JSON array, or an NDJSON stream when the Content-Type says so, of
+/// `IPParams` jobs and answers in the same shape.
+pub async fn batch_ip_ops(headers: HeaderMap, data: Bytes) -> Result<Response, BatchError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|header_type| header_type.to_str().ok())
+        .and_then(|header_type| header_type.split(';').next())
+        .map(str::trim);
+    let ndjson = content_type.is_some_and(|content_type| NDJSON_MIME_TYPES.contains(&content_type));
+
+    let reports = run_batch(ndjson, &data)?;
+    if ndjson {
+        let mut lines = String::new();
+        for report in reports {
+            lines.push_str(&report.to_string());
+            lines.push('\n');
+        }
+        Ok(([(header::CONTENT_TYPE, NDJSON_MIME_TYPES[0])], lines).into_response())
+    } else {
+        Ok(Json(reports).into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cipher::{IpMode, IpOp};
+    use super::*;
+
+    fn params(from: Option<&str>, key: Option<&str>, to: Option<&str>) -> IPParams {
+        IPParams {
+            from: from.map(str::to_owned),
+            key: key.map(str::to_owned),
+            to: to.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    fn op_params(
+        from: &str,
+        key: Option<&str>,
+        to: Option<&str>,
+        op: &str,
+        mode: &str,
+    ) -> IPParams {
+        IPParams {
+            op: Some(op.to_owned()),
+            mode: Some(mode.to_owned()),
+            ..params(Some(from), key, to)
+        }
+    }
+
+    #[test]
+    fn test_task1_dest() {
+        let res = calc_ip(&params(Some("10.0.0.0"), Some("1.2.3.255"), None));
+        assert_eq!(Ok("11.2.3.255".to_owned()), res);
+    }
+
+    #[test]
+    fn test_task2_key() {
+        let res = calc_ip(&params(Some("10.0.0.0"), None, Some("11.2.3.255")));
+        assert_eq!(Ok("1.2.3.255".to_owned()), res);
+    }
+
+    #[test]
+    fn test_task3_v6() {
+        let res = calc_ip(&params(Some("fe80::1"), Some("5:6:7::3333"), None));
+        assert_eq!(Ok("fe85:6:7::3332".to_owned()), res);
+    }
+
+    #[test]
+    fn test_invalid_from() {
+        let res = calc_ip(&params(Some("10.0.0.256"), Some("1.2.3.4"), None));
+        assert_eq!(
+            Err(IpOpsError::InvalidAddress {
+                param: "from",
+                value: "10.0.0.256".to_owned()
+            }),
+            res
+        );
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        let res = calc_ip(&params(Some("fe80::1"), Some("not-an-ip"), None));
+        assert_eq!(
+            Err(IpOpsError::InvalidAddress {
+                param: "key",
+                value: "not-an-ip".to_owned()
+            }),
+            res
+        );
+    }
+
+    #[test]
+    fn test_invalid_to() {
+        let res = calc_ip(&params(Some("10.0.0.0"), None, Some("")));
+        assert_eq!(
+            Err(IpOpsError::InvalidAddress {
+                param: "to",
+                value: "".to_owned()
+            }),
+            res
+        );
+    }
+
+    #[test]
+    fn test_family_mismatch() {
+        let res = calc_ip(&params(Some("10.0.0.0"), Some("::1"), None));
+        assert_eq!("family_mismatch", res.unwrap_err().code());
+        let res = calc_ip(&params(Some("::1"), None, Some("10.0.0.0")));
+        assert_eq!(
+            Err(IpOpsError::FamilyMismatch {
+                base: "from",
+                base_addr: "::1".parse().unwrap(),
+                param: "to",
+                param_addr: "10.0.0.0".parse().unwrap(),
+            }),
+            res
+        );
+    }
+
+    #[test]
+    fn test_missing_operands() {
+        assert_eq!(
+            Err(IpOpsError::MissingOperand { params: &["from"] }),
+            calc_ip(&params(None, Some("1.2.3.4"), None))
+        );
+        assert_eq!(
+            Err(IpOpsError::MissingOperand { params: OPERANDS }),
+            calc_ip(&params(Some("10.0.0.0"), None, None))
+        );
+    }
+
+    #[test]
+    fn test_conflicting_operands() {
+        let res = calc_ip(&params(Some("10.0.0.0"), Some("1.2.3.4"), Some("11.2.3.4")));
+        assert_eq!(
+            Err(IpOpsError::ConflictingOperands { params: OPERANDS }),
+            res
+        );
+    }
+
+    #[test]
+    fn test_cidr_dest() {
+        let res = calc_ip(&params(Some("10.0.0.7/24"), Some("1.2.3.255/24"), None));
+        assert_eq!(Ok("11.2.3.7/24".to_owned()), res);
+        let res = calc_ip(&params(Some("10.0.0.0/24"), Some("1.2.3.4"), None));
+        assert_eq!(Ok("11.2.3.0/24".to_owned()), res);
+    }
+
+    #[test]
+    fn test_cidr_key() {
+        let res = calc_ip(&params(Some("10.0.0.0/24"), None, Some("11.2.3.0/24")));
+        assert_eq!(Ok("1.2.3.0/24".to_owned()), res);
+        let res = calc_ip(&params(Some("fe80::/64"), None, Some("fe85:6:7::/64")));
+        assert_eq!(Ok("5:6:7::/64".to_owned()), res);
+    }
+
+    #[test]
+    fn test_cidr_prefix_mismatch() {
+        let res = calc_ip(&params(Some("10.0.0.0/24"), Some("1.2.3.0/16"), None));
+        assert_eq!(
+            Err(IpOpsError::PrefixMismatch {
+                base: "from",
+                base_prefix: 24,
+                param: "key",
+                param_prefix: 16,
+            }),
+            res
+        );
+        let res = calc_ip(&params(Some("10.0.0.0/33"), Some("1.2.3.0"), None));
+        assert_eq!("invalid_address", res.unwrap_err().code());
+    }
+
+    #[test]
+    fn test_ops_both_families() {
+        let res = calc_ip(&op_params(
+            "10.0.0.1",
+            Some("0.0.0.255"),
+            None,
+            "add",
+            "int",
+        ));
+        assert_eq!(Ok("10.0.1.0".to_owned()), res);
+        let res = calc_ip(&op_params("::ff", Some("::1"), None, "add", "octet"));
+        assert_eq!(Ok("::".to_owned()), res);
+        let res = calc_ip(&op_params("::ff", Some("::1"), None, "add", "int"));
+        assert_eq!(Ok("::100".to_owned()), res);
+        let res = calc_ip(&op_params(
+            "10.0.0.1",
+            Some("0.0.0.2"),
+            None,
+            "sub",
+            "octet",
+        ));
+        assert_eq!(Ok("10.0.0.255".to_owned()), res);
+        let res = calc_ip(&op_params(
+            "10.0.0.1",
+            Some("1.1.1.1"),
+            None,
+            "xor",
+            "group",
+        ));
+        assert_eq!(Ok("11.1.1.0".to_owned()), res);
+        let res = calc_ip(&op_params(
+            "128.0.0.1",
+            Some("0.0.0.1"),
+            None,
+            "rotate",
+            "int",
+        ));
+        assert_eq!(Ok("0.0.0.3".to_owned()), res);
+    }
+
+    #[test]
+    fn test_ops_key_roundtrip() {
+        for op in IpOp::NAMES {
+            for mode in IpMode::NAMES {
+                for (from, key) in [("10.20.30.40", "1.2.3.4"), ("fe80::1", "5:6:7::3333")] {
+                    let to = calc_ip(&op_params(from, Some(key), None, op, mode)).unwrap();
+                    let derived = calc_ip(&op_params(from, None, Some(&to), op, mode)).unwrap();
+                    let again = calc_ip(&op_params(from, Some(&derived), None, op, mode));
+                    assert_eq!(Ok(to), again, "{} {} {}", op, mode, from);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_ops_invalid() {
+        let res = calc_ip(&op_params(
+            "10.0.0.1",
+            Some("1.2.3.4"),
+            None,
+            "mul",
+            "octet",
+        ));
+        assert_eq!("invalid_parameter", res.unwrap_err().code());
+        let res = calc_ip(&op_params(
+            "10.0.0.1",
+            Some("1.2.3.4"),
+            None,
+            "add",
+            "nibble",
+        ));
+        assert_eq!("invalid_parameter", res.unwrap_err().code());
+        let res = calc_ip(&op_params(
+            "10.0.0.0/8",
+            Some("1.0.0.0"),
+            None,
+            "rotate",
+            "int",
+        ));
+        assert_eq!("unsupported_operation", res.unwrap_err().code());
+        let res = calc_ip(&op_params(
+            "0.0.0.1",
+            None,
+            Some("0.0.0.3"),
+            "rotate",
+            "octet",
+        ));
+        assert_eq!("key_not_found", res.unwrap_err().code());
+    }
+
+    #[test]
+    fn test_cipher_by_name() {
+        let cipher_params = |from: Option<&str>, key, to: Option<&str>| IPParams {
+            cipher: Some("feistel".to_owned()),
+            ..params(from, key, to)
+        };
+        let to = calc_ip(&cipher_params(Some("10.0.0.1"), Some("1.2.3.4"), None)).unwrap();
+        let to_addr: IpAddr = to.parse().unwrap();
+        assert!(to_addr.is_ipv4());
+        assert_eq!(
+            Ok("10.0.0.1".to_owned()),
+            calc_ip(&cipher_params(None, Some("1.2.3.4"), Some(&to)))
+        );
+        assert_eq!(
+            "unsupported_operation",
+            calc_ip(&cipher_params(Some("10.0.0.1"), None, Some(&to)))
+                .unwrap_err()
+                .code()
+        );
+
+        let res = calc_ip(&IPParams {
+            cipher: Some("group-xor".to_owned()),
+            ..params(Some("fe80::1"), Some("5:6:7::3333"), None)
+        });
+        assert_eq!(Ok("fe85:6:7::3332".to_owned()), res);
+        let res = calc_ip(&IPParams {
+            cipher: Some("octet-add".to_owned()),
+            op: Some("xor".to_owned()),
+            ..params(Some("10.0.0.0"), Some("1.2.3.4"), None)
+        });
+        assert_eq!("conflicting_operands", res.unwrap_err().code());
+        let res = calc_ip(&IPParams {
+            cipher: Some("enigma".to_owned()),
+            ..params(Some("10.0.0.0"), Some("1.2.3.4"), None)
+        });
+        assert_eq!("invalid_parameter", res.unwrap_err().code());
+    }
+
+    #[test]
+    fn test_decrypt() {
+        let res = calc_ip(&params(None, Some("1.2.3.255"), Some("11.2.3.255")));
+        assert_eq!(Ok("10.0.0.0".to_owned()), res);
+        let res = calc_ip(&params(
+            None,
+            Some("5:6:7::3333/64"),
+            Some("fe85:6:7::3332/64"),
+        ));
+        assert_eq!(Ok("fe80::3332/64".to_owned()), res);
+    }
+
+    #[test]
+    fn test_client_address() {
+        // The client's address is taken as is, not read back through `in`.
+        let ip_params = IPParams {
+            key: Some("4294967296".to_owned()),
+            input: Some("int".to_owned()),
+            ..IPParams::default()
+        };
+        let res = calc_ip_for(&ip_params, Some("::1".parse().unwrap()));
+        assert_eq!(Ok("::1:0:1".to_owned()), res);
+        let res = calc_ip_for(&params(None, None, None), Some("10.0.0.0".parse().unwrap()));
+        assert_eq!("missing_operand", res.unwrap_err().code());
+    }
+
+    #[test]
+    fn test_notations() {
+        let res = calc_ip(&IPParams {
+            input: Some("int".to_owned()),
+            output: Some("ptr".to_owned()),
+            ..params(Some("167772160"), Some("16909311"), None)
+        });
+        assert_eq!(Ok("255.3.2.11.in-addr.arpa".to_owned()), res);
+        let res = calc_ip(&IPParams {
+            output: Some("expanded".to_owned()),
+            ..params(Some("fe80::1"), Some("5:6:7::3333"), None)
+        });
+        assert_eq!(
+            Ok("fe85:0006:0007:0000:0000:0000:0000:3332".to_owned()),
+            res
+        );
+        let res = calc_ip(&IPParams {
+            input: Some("hex".to_owned()),
+            ..params(Some("10.0.0.0"), Some("1.2.3.255"), None)
+        });
+        assert_eq!("invalid_address", res.unwrap_err().code());
+        let res = calc_ip(&IPParams {
+            output: Some("roman".to_owned()),
+            ..params(Some("10.0.0.0"), Some("1.2.3.255"), None)
+        });
+        assert_eq!("invalid_parameter", res.unwrap_err().code());
+    }
+
+    #[test]
+    fn test_batch_json() {
+        let data = br#"[
+            {"from": "10.0.0.0", "key": "1.2.3.255"},
+            {"from": "10.0.0.0", "to": "11.2.3.255"},
+            {"from": "10.0.0.0"},
+            {"from": 10},
+            {"from": "fe80::1", "key": "5:6:7::3333", "op": "xor"}
+        ]"#;
+        let reports = run_batch(false, data).unwrap();
+        assert_eq!(5, reports.len());
+        assert_eq!("11.2.3.255", reports[0]["result"]);
+        assert_eq!("1.2.3.255", reports[1]["result"]);
+        assert_eq!("missing_operand", reports[2]["error"]["error"]);
+        assert_eq!("invalid_job", reports[3]["error"]["error"]);
+        assert_eq!("fe85:6:7::3332", reports[4]["result"]);
+        assert_eq!(4, reports[4]["index"]);
+    }
+
+    #[test]
+    fn test_batch_ndjson() {
+        let data = b"{\"from\": \"10.0.0.0\", \"key\": \"1.2.3.255\"}\n\nnot json\n{\"from\": \"::1\", \"key\": \"1.2.3.4\"}\n";
+        let reports = run_batch(true, data).unwrap();
+        assert_eq!(3, reports.len());
+        assert_eq!("11.2.3.255", reports[0]["result"]);
+        assert_eq!("invalid_job", reports[1]["error"]["error"]);
+        assert_eq!("family_mismatch", reports[2]["error"]["error"]);
+        assert_eq!(
+            "invalid_batch",
+            run_batch(false, b"{\"from\": \"10.0.0.0\"}")
+                .unwrap_err()
+                .code()
+        );
+    }
+
+    #[test]
+    fn test_error_response() {
+        let err = IpOpsError::InvalidAddress {
+            param: "from",
+            value: "x".to_owned(),
+        };
+        assert_eq!("from", err.to_json()["param"]);
+        assert_eq!("invalid_address", err.to_json()["error"]);
+        assert_eq!(StatusCode::BAD_REQUEST, err.into_response().status());
+    }
+}
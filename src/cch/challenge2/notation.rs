@@ -0,0 +1,339 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use ipnet::IpNet;
+
+use super::{addr_to_bits, IpOpsError};
+
+/// Bits an IPv4 address is shifted by inside an IPv4-mapped IPv6 address.
+const MAPPED_PREFIX_LEN: u8 = 96;
+
+const IPV4_PTR_SUFFIX: &str = ".in-addr.arpa";
+const IPV6_PTR_SUFFIX: &str = ".ip6.arpa";
+
+/// The ways an address can be written on the way in (`in`) or out (`out`).
+///
+/// The integer notations carry no family of their own: decimal values above
+/// `u32::MAX`, and hex or binary strings longer than 8 or 32 digits, are read
+/// as IPv6, everything else as IPv4. They are always written at full width so
+/// that what comes out reads back as the same family.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Notation {
+    /// `10.0.0.1`, `fe80::1`
+    #[default]
+    Text,
+    /// `167772161`
+    Int,
+    /// `0x0a000001`
+    Hex,
+    /// `0b00001010000000000000000000000001`
+    Bin,
+    /// `010.000.000.001`, `fe80:0000:0000:0000:0000:0000:0000:0001`
+    Expanded,
+    /// `::ffff:10.0.0.1`
+    Mapped,
+    /// `1.0.0.10.in-addr.arpa`, `1.0.[...].8.e.f.ip6.arpa`
+    Ptr,
+}
+impl Notation {
+    pub const NAMES: &'static [&'static str] =
+        &["text", "int", "hex", "bin", "expanded", "mapped", "ptr"];
+
+    pub fn parse(param: &'static str, value: Option<&str>) -> Result<Self, IpOpsError> {
+        match value {
+            None | Some("text") => Ok(Self::Text),
+            Some("int") => Ok(Self::Int),
+            Some("hex") => Ok(Self::Hex),
+            Some("bin") => Ok(Self::Bin),
+            Some("expanded") => Ok(Self::Expanded),
+            Some("mapped") => Ok(Self::Mapped),
+            Some("ptr") => Ok(Self::Ptr),
+            Some(value) => Err(IpOpsError::InvalidParameter {
+                param,
+                value: value.to_owned(),
+                expected: Self::NAMES,
+            }),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Int => "int",
+            Self::Hex => "hex",
+            Self::Bin => "bin",
+            Self::Expanded => "expanded",
+            Self::Mapped => "mapped",
+            Self::Ptr => "ptr",
+        }
+    }
+
+    /// Reads an address, with an optional `/len` suffix except for `ptr`
+    /// names, returning `None` when it is not valid in this notation.
+    pub fn read(self, value: &str) -> Option<(IpAddr, Option<u8>)> {
+        let (value, prefix_len) = match value.split_once('/') {
+            Some((value, prefix_len)) => (value, Some(prefix_len.parse::<u8>().ok()?)),
+            None => (value, None),
+        };
+        let (addr, prefix_len) = match self {
+            Self::Text => (value.parse().ok()?, prefix_len),
+            Self::Int => (read_int(value)?, prefix_len),
+            Self::Hex => (read_radix(value, &["0x", "0X"], 16, 8)?, prefix_len),
+            Self::Bin => (read_radix(value, &["0b", "0B"], 2, 32)?, prefix_len),
+            Self::Expanded => (read_expanded(value)?, prefix_len),
+            Self::Mapped => read_mapped(value, prefix_len)?,
+            Self::Ptr if prefix_len.is_none() => (read_ptr(value)?, None),
+            Self::Ptr => return None,
+        };
+        if let Some(prefix_len) = prefix_len {
+            IpNet::new(addr, prefix_len).ok()?;
+        }
+        Some((addr, prefix_len))
+    }
+
+    /// Writes an address, and its prefix length when there is one.
+    pub fn write(self, addr: IpAddr, prefix_len: Option<u8>) -> Result<String, IpOpsError> {
+        let width = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let bits = addr_to_bits(addr);
+        let (written, prefix_len) = match self {
+            Self::Text => (addr.to_string(), prefix_len),
+            Self::Int => (bits.to_string(), prefix_len),
+            Self::Hex => (
+                format!("0x{:0width$x}", bits, width = width / 4),
+                prefix_len,
+            ),
+            Self::Bin => (format!("0b{:0width$b}", bits, width = width), prefix_len),
+            Self::Expanded => (write_expanded(addr), prefix_len),
+            Self::Mapped => match addr {
+                IpAddr::V4(addr) => (
+                    addr.to_ipv6_mapped().to_string(),
+                    prefix_len.map(|prefix_len| prefix_len + MAPPED_PREFIX_LEN),
+                ),
+                IpAddr::V6(_) => (addr.to_string(), prefix_len),
+            },
+            Self::Ptr => {
+                return write_ptr(addr, prefix_len).ok_or(IpOpsError::Unrepresentable {
+                    notation: self.name(),
+                    value: match prefix_len {
+                        Some(prefix_len) => format!("{}/{}", addr, prefix_len),
+                        None => addr.to_string(),
+                    },
+                })
+            }
+        };
+        Ok(match prefix_len {
+            Some(prefix_len) => format!("{}/{}", written, prefix_len),
+            None => written,
+        })
+    }
+}
+
+fn int_to_addr(bits: u128, force_v6: bool) -> IpAddr {
+    if !force_v6 && bits <= u32::MAX as u128 {
+        IpAddr::V4(Ipv4Addr::from(bits as u32))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(bits))
+    }
+}
+
+fn read_int(value: &str) -> Option<IpAddr> {
+    // `u128::from_str` also takes a leading `+`.
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(int_to_addr(value.parse().ok()?, false))
+}
+
+fn read_radix(value: &str, prefixes: &[&str], radix: u32, v4_digits: usize) -> Option<IpAddr> {
+    let digits = prefixes
+        .iter()
+        .find_map(|prefix| value.strip_prefix(prefix))
+        .unwrap_or(value);
+    if digits.is_empty() || digits.starts_with('+') {
+        return None;
+    }
+    let bits = u128::from_str_radix(digits, radix).ok()?;
+    Some(int_to_addr(bits, digits.len() > v4_digits))
+}
+
+fn read_expanded(value: &str) -> Option<IpAddr> {
+    if value.contains(':') {
+        return value.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    // Leading zeros are what makes this notation, and `Ipv4Addr` refuses them.
+    let mut octets = [0u8; 4];
+    let mut parts = value.split('.');
+    for octet in octets.iter_mut() {
+        let part = parts.next()?;
+        if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        *octet = part.parse().ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(IpAddr::V4(Ipv4Addr::from(octets))),
+    }
+}
+
+fn write_expanded(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => addr.octets().map(|octet| format!("{:03}", octet)).join("."),
+        IpAddr::V6(addr) => addr
+            .segments()
+            .map(|segment| format!("{:04x}", segment))
+            .join(":"),
+    }
+}
+
+fn read_mapped(value: &str, prefix_len: Option<u8>) -> Option<(IpAddr, Option<u8>)> {
+    match value.parse::<IpAddr>().ok()? {
+        IpAddr::V4(addr) => Some((IpAddr::V4(addr), prefix_len)),
+        IpAddr::V6(addr) => {
+            let addr = addr.to_ipv4_mapped()?;
+            let prefix_len = match prefix_len {
+                Some(prefix_len) => Some(prefix_len.checked_sub(MAPPED_PREFIX_LEN)?),
+                None => None,
+            };
+            Some((IpAddr::V4(addr), prefix_len))
+        }
+    }
+}
+
+fn read_ptr(value: &str) -> Option<IpAddr> {
+    let value = value.to_ascii_lowercase();
+    let value = value.strip_suffix('.').unwrap_or(&value);
+    if let Some(labels) = value.strip_suffix(IPV4_PTR_SUFFIX) {
+        let labels: Vec<&str> = labels.split('.').collect();
+        if labels.len() != 4 {
+            return None;
+        }
+        return read_expanded(&labels.into_iter().rev().collect::<Vec<_>>().join("."));
+    }
+    let labels = value.strip_suffix(IPV6_PTR_SUFFIX)?;
+    let nibbles: Vec<&str> = labels.split('.').collect();
+    let is_nibble =
+        |nibble: &&str| nibble.len() == 1 && nibble.chars().all(|c| c.is_ascii_hexdigit());
+    if nibbles.len() != 32 || !nibbles.iter().all(is_nibble) {
+        return None;
+    }
+    let digits: String = nibbles.into_iter().rev().collect();
+    u128::from_str_radix(&digits, 16)
+        .ok()
+        .map(|bits| IpAddr::V6(Ipv6Addr::from(bits)))
+}
+
+/// Writes the reverse-DNS name of an address, or of the zone delegated for a
+/// prefix. Prefixes that do not end on a label boundary (a whole octet, or a
+/// whole nibble for IPv6) have no such zone.
+fn write_ptr(addr: IpAddr, prefix_len: Option<u8>) -> Option<String> {
+    let (labels, label_bits, suffix): (Vec<String>, u8, &str) = match addr {
+        IpAddr::V4(addr) => (
+            addr.octets().map(|octet| octet.to_string()).to_vec(),
+            8,
+            IPV4_PTR_SUFFIX,
+        ),
+        IpAddr::V6(addr) => (
+            format!("{:032x}", u128::from(addr))
+                .chars()
+                .map(String::from)
+                .collect(),
+            4,
+            IPV6_PTR_SUFFIX,
+        ),
+    };
+    let kept = match prefix_len {
+        Some(prefix_len) if prefix_len % label_bits != 0 => return None,
+        Some(prefix_len) => (prefix_len / label_bits) as usize,
+        None => labels.len(),
+    };
+    let mut name: Vec<String> = labels[..kept].iter().rev().cloned().collect();
+    name.push(suffix[1..].to_owned());
+    Some(name.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(notation: &str, value: &str) -> Option<(IpAddr, Option<u8>)> {
+        Notation::parse("in", Some(notation)).unwrap().read(value)
+    }
+
+    fn write(notation: &str, value: &str) -> String {
+        let (addr, prefix_len) = Notation::Text.read(value).unwrap();
+        Notation::parse("out", Some(notation))
+            .unwrap()
+            .write(addr, prefix_len)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read() {
+        let v4: IpAddr = "10.0.0.1".parse().unwrap();
+        let v6: IpAddr = "fe80::1".parse().unwrap();
+        assert_eq!(Some((v4, None)), read("int", "167772161"));
+        assert_eq!(Some((v4, Some(8))), read("hex", "0x0a000001/8"));
+        assert_eq!(
+            Some((v4, None)),
+            read("bin", "1010000000000000000000000001")
+        );
+        assert_eq!(Some((v4, None)), read("expanded", "010.000.000.001"));
+        assert_eq!(Some((v4, Some(24))), read("mapped", "::ffff:10.0.0.1/120"));
+        assert_eq!(Some((v4, None)), read("ptr", "1.0.0.10.in-addr.arpa."));
+        assert_eq!(
+            Some((v6, None)),
+            read("hex", "0xfe800000000000000000000000000001")
+        );
+        assert_eq!(
+            Some((v6, None)),
+            read(
+                "ptr",
+                "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.E.F.IP6.ARPA"
+            )
+        );
+        assert_eq!(
+            Some(("::1".parse().unwrap(), None)),
+            read("hex", "0x000000001")
+        );
+
+        assert_eq!(None, read("text", "010.000.000.001"));
+        assert_eq!(None, read("int", "-1"));
+        assert_eq!(None, read("int", "+167772161"));
+        assert_eq!(None, read("hex", "0x0a000001/33"));
+        assert_eq!(None, read("mapped", "fe80::1"));
+        assert_eq!(None, read("ptr", "1.0.10.in-addr.arpa"));
+        assert_eq!(
+            None,
+            read(
+                "ptr",
+                "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.e.+.ip6.arpa"
+            )
+        );
+    }
+
+    #[test]
+    fn test_write() {
+        assert_eq!("167772161/8", write("int", "10.0.0.1/8"));
+        assert_eq!("0x0a000001", write("hex", "10.0.0.1"));
+        assert_eq!(
+            "0b00001010000000000000000000000001",
+            write("bin", "10.0.0.1")
+        );
+        assert_eq!("010.000.000.001", write("expanded", "10.0.0.1"));
+        assert_eq!(
+            "fe80:0000:0000:0000:0000:0000:0000:0001",
+            write("expanded", "fe80::1")
+        );
+        assert_eq!("::ffff:10.0.0.0/104", write("mapped", "10.0.0.0/8"));
+        assert_eq!("1.0.0.10.in-addr.arpa", write("ptr", "10.0.0.1"));
+        assert_eq!("0.10.in-addr.arpa", write("ptr", "10.0.0.0/16"));
+        assert_eq!("8.b.d.0.1.0.0.2.ip6.arpa", write("ptr", "2001:db8::/32"));
+        assert!(Notation::Ptr
+            .write("10.0.0.0".parse().unwrap(), Some(20))
+            .is_err());
+        assert!(Notation::parse("out", Some("octal")).is_err());
+    }
+}
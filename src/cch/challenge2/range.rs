@@ -0,0 +1,506 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{
+    addr_to_bits, bits_to_addr,
+    notation::Notation,
+    subnet::{parse_number, parse_prefix},
+    IpOpsError,
+};
+
+/// How many entries an expansion returns unless `limit` says otherwise.
+const DEFAULT_LIMIT: u128 = 1024;
+/// Largest `limit` a client may ask for.
+const MAX_LIMIT: u128 = 65_536;
+
+const EXPAND_SHAPES: &[&str] = &["addresses", "cidrs"];
+const COMPRESS_SHAPES: &[&str] = &["ranges", "cidrs"];
+
+/// A range that would produce more entries than allowed gets its own code;
+/// every other error is an [`IpOpsError`].
+#[derive(Debug, PartialEq)]
+pub enum RangeError {
+    TooLarge {
+        param: &'static str,
+        value: String,
+        limit: u128,
+    },
+    Params(IpOpsError),
+}
+impl RangeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::TooLarge { .. } => "too_large",
+            Self::Params(e) => e.code(),
+        }
+    }
+    fn to_json(&self) -> Value {
+        match self {
+            Self::TooLarge {
+                param,
+                value,
+                limit,
+            } => json!({
+                "error": self.code(),
+                "message": self.to_string(),
+                "param": param,
+                "value": value,
+                "limit": limit.to_string(),
+            }),
+            Self::Params(e) => e.to_json(),
+        }
+    }
+}
+impl From<IpOpsError> for RangeError {
+    fn from(e: IpOpsError) -> Self {
+        Self::Params(e)
+    }
+}
+impl Display for RangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLarge {
+                param,
+                value,
+                limit,
+            } => write!(
+                f,
+                "`{}` ({:?}) expands to more than {} entries",
+                param, value, limit
+            ),
+            Self::Params(e) => e.fmt(f),
+        }
+    }
+}
+impl IntoResponse for RangeError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, Json(self.to_json())).into_response()
+    }
+}
+
+/// An inclusive run of addresses of a single family. Spans sort IPv4 first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Span {
+    v6: bool,
+    start: u128,
+    end: u128,
+}
+impl Span {
+    fn of_net(net: IpNet) -> Self {
+        Self {
+            v6: net.addr().is_ipv6(),
+            start: addr_to_bits(net.network()),
+            end: addr_to_bits(net.broadcast()),
+        }
+    }
+
+    fn addr(&self, bits: u128) -> IpAddr {
+        let like = match self.v6 {
+            true => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            false => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        };
+        bits_to_addr(like, bits)
+    }
+
+    fn width(&self) -> u32 {
+        if self.v6 {
+            128
+        } else {
+            32
+        }
+    }
+
+    /// The fewest prefixes covering exactly this span: at each step, the
+    /// largest block aligned on `start` that does not run past `end`.
+    fn cidrs(&self) -> Vec<IpNet> {
+        let mut cidrs = vec![];
+        let mut start = self.start;
+        loop {
+            let mut host_bits = start.trailing_zeros().min(self.width());
+            let block_end = loop {
+                let mask = u128::MAX.checked_shr(128 - host_bits).unwrap_or(0);
+                if start | mask <= self.end {
+                    break start | mask;
+                }
+                host_bits -= 1;
+            };
+            let prefix_len = (self.width() - host_bits) as u8;
+            cidrs.push(IpNet::new(self.addr(start), prefix_len).expect("prefix fits the family"));
+            if block_end >= self.end {
+                return cidrs;
+            }
+            start = block_end + 1;
+        }
+    }
+}
+
+/// Reads `a-b`, a prefix, a bare address or an IPv4 wildcard. Wildcards can
+/// stand for millions of spans, so at most `max_spans + 1` are produced:
+/// enough for the caller to tell it went over.
+fn parse_range(param: &'static str, value: &str, max_spans: u128) -> Result<Vec<Span>, IpOpsError> {
+    let invalid = || IpOpsError::InvalidAddress {
+        param,
+        value: value.to_owned(),
+    };
+    let value = value.trim();
+    if let Some((first, last)) = value.split_once('-') {
+        let first = first.trim().parse::<IpAddr>().map_err(|_| invalid())?;
+        let last = last.trim().parse::<IpAddr>().map_err(|_| invalid())?;
+        if first.is_ipv4() != last.is_ipv4() {
+            return Err(IpOpsError::FamilyMismatch {
+                base: param,
+                base_addr: first,
+                param,
+                param_addr: last,
+            });
+        }
+        let (start, end) = (addr_to_bits(first), addr_to_bits(last));
+        if start > end {
+            return Err(invalid());
+        }
+        return Ok(vec![Span {
+            v6: first.is_ipv6(),
+            start,
+            end,
+        }]);
+    }
+    if value.contains('*') {
+        return parse_wildcard(value, max_spans).ok_or_else(invalid);
+    }
+    Ok(vec![Span::of_net(parse_prefix(param, value)?)])
+}
+
+/// Expands an IPv4 wildcard such as `10.0.*.1`. Each run of trailing `*`
+/// stays a single span; every other `*` multiplies the spans by 256.
+fn parse_wildcard(value: &str, max_spans: u128) -> Option<Vec<Span>> {
+    let octets = value
+        .split('.')
+        .map(|octet| match octet {
+            "*" => Ok(None),
+            octet => octet.parse::<u8>().map(Some),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    if octets.len() != 4 {
+        return None;
+    }
+    let trailing = octets
+        .iter()
+        .rev()
+        .take_while(|octet| octet.is_none())
+        .count();
+    let inner: Vec<usize> = (0..4 - trailing).filter(|&i| octets[i].is_none()).collect();
+    let host_mask = u128::MAX
+        .checked_shr(128 - 8 * trailing as u32)
+        .unwrap_or(0);
+    let count = 256u128.pow(inner.len() as u32);
+
+    let spans = (0..count.min(max_spans + 1))
+        .map(|mut index| {
+            let mut filled = [0u8; 4];
+            for (i, octet) in octets.iter().enumerate() {
+                filled[i] = octet.unwrap_or(0);
+            }
+            for &i in inner.iter().rev() {
+                filled[i] = (index % 256) as u8;
+                index /= 256;
+            }
+            let start = u32::from(Ipv4Addr::from(filled)) as u128;
+            Span {
+                v6: false,
+                start,
+                end: start | host_mask,
+            }
+        })
+        .collect();
+    Some(spans)
+}
+
+/// Sorts spans and merges the ones that overlap or touch.
+fn merge(mut spans: Vec<Span>) -> Vec<Span> {
+    spans.sort();
+    let mut merged: Vec<Span> = vec![];
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if last.v6 == span.v6 && span.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(span.end);
+            }
+            _ => merged.push(span),
+        }
+    }
+    merged
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RangeParams {
+    range: Option<String>,
+    #[serde(rename = "as")]
+    shape: Option<String>,
+    limit: Option<String>,
+    #[serde(rename = "out")]
+    output: Option<String>,
+}
+
+fn parse_shape(
+    value: Option<&str>,
+    expected: &'static [&'static str],
+) -> Result<&'static str, IpOpsError> {
+    match value {
+        None => Ok(expected[0]),
+        Some(value) => expected
+            .iter()
+            .find(|shape| shape.eq_ignore_ascii_case(value))
+            .copied()
+            .ok_or(IpOpsError::InvalidParameter {
+                param: "as",
+                value: value.to_owned(),
+                expected,
+            }),
+    }
+}
+
+/// Every address (or every minimal prefix) in `range`, refusing to produce
+/// more than `limit` of them.
+fn expand(range_params: &RangeParams) -> Result<Vec<String>, RangeError> {
+    let range = range_params
+        .range
+        .as_deref()
+        .ok_or(IpOpsError::MissingOperand { params: &["range"] })?;
+    let shape = parse_shape(range_params.shape.as_deref(), EXPAND_SHAPES)?;
+    let limit = match range_params.limit.as_deref() {
+        Some(limit) => parse_number("limit", limit, 1, MAX_LIMIT)?,
+        None => DEFAULT_LIMIT,
+    };
+    let notation = Notation::parse("out", range_params.output.as_deref())?;
+    let too_large = || RangeError::TooLarge {
+        param: "range",
+        value: range.to_owned(),
+        limit,
+    };
+
+    let spans = parse_range("range", range, limit)?;
+    let mut entries = vec![];
+    for span in spans {
+        if shape == "cidrs" {
+            for net in span.cidrs() {
+                entries.push(notation.write(net.addr(), Some(net.prefix_len()))?);
+            }
+        } else {
+            if span.end - span.start >= limit {
+                return Err(too_large());
+            }
+            for bits in span.start..=span.end {
+                entries.push(notation.write(span.addr(bits), None)?);
+            }
+        }
+        if entries.len() as u128 > limit {
+            return Err(too_large());
+        }
+    }
+    Ok(entries)
+}
+
+/// Folds addresses, prefixes and ranges into the fewest ranges or prefixes.
+/// The values may not add up to more than `MAX_LIMIT` spans before merging;
+/// the one that goes over is reported.
+fn compress(values: &[String], shape: &str) -> Result<Vec<String>, RangeError> {
+    let mut spans = vec![];
+    for value in values {
+        let remaining = MAX_LIMIT - spans.len() as u128;
+        spans.extend(parse_range("addresses", value, remaining)?);
+        if spans.len() as u128 > MAX_LIMIT {
+            return Err(RangeError::TooLarge {
+                param: "addresses",
+                value: value.to_owned(),
+                limit: MAX_LIMIT,
+            });
+        }
+    }
+    let spans = merge(spans);
+    Ok(match shape {
+        "cidrs" => spans
+            .iter()
+            .flat_map(Span::cidrs)
+            .map(|net| net.to_string())
+            .collect(),
+        _ => spans
+            .iter()
+            .map(|span| match span.start == span.end {
+                true => span.addr(span.start).to_string(),
+                false => format!("{}-{}", span.addr(span.start), span.addr(span.end)),
+            })
+            .collect(),
+    })
+}
+
+/// Expands `range` (`a-b`, `10.0.*.1` or a prefix) into addresses, or with
+/// `as=cidrs` into the minimal prefixes covering it.
+pub async fn expand_range(
+    range_params: Query<RangeParams>,
+) -> Result<impl IntoResponse, RangeError> {
+    let entries = expand(&range_params.0)?;
+    let key = parse_shape(range_params.shape.as_deref(), EXPAND_SHAPES)?;
+    Ok(Json(json!({ "count": entries.len(), key: entries })))
+}
+
+/// Compresses a JSON array of addresses, prefixes and ranges into ranges, or
+/// with `as=cidrs` into prefixes.
+pub async fn compress_range(
+    range_params: Query<RangeParams>,
+    Json(values): Json<Vec<String>>,
+) -> Result<impl IntoResponse, RangeError> {
+    let shape = parse_shape(range_params.shape.as_deref(), COMPRESS_SHAPES)?;
+    let entries: Value = compress(&values, shape)?.into();
+    Ok(Json(json!({ shape: entries })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(range: &str, shape: Option<&str>, limit: Option<&str>) -> RangeParams {
+        RangeParams {
+            range: Some(range.to_owned()),
+            shape: shape.map(str::to_owned),
+            limit: limit.map(str::to_owned),
+            output: None,
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_expand_range() {
+        assert_eq!(
+            strings(&["10.0.0.254", "10.0.0.255", "10.0.1.0"]),
+            expand(&params("10.0.0.254 - 10.0.1.0", None, None)).unwrap()
+        );
+        assert_eq!(
+            strings(&[
+                "10.0.0.1/32",
+                "10.0.0.2/31",
+                "10.0.0.4/30",
+                "10.0.0.8/29",
+                "10.0.0.16/30",
+                "10.0.0.20/32"
+            ]),
+            expand(&params("10.0.0.1-10.0.0.20", Some("cidrs"), None)).unwrap()
+        );
+        assert_eq!(
+            strings(&["::/0"]),
+            expand(&params(
+                ":: - ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+                Some("cidrs"),
+                None
+            ))
+            .unwrap()
+        );
+        assert_eq!(
+            strings(&["2001:db8::", "2001:db8::1"]),
+            expand(&params("2001:db8::1/127", None, None)).unwrap()
+        );
+        let err = expand(&params("10.0.0.2-10.0.0.1", None, None)).unwrap_err();
+        assert_eq!("invalid_address", err.code());
+        let err = expand(&params("10.0.0.1-::1", None, None)).unwrap_err();
+        assert_eq!("family_mismatch", err.code());
+    }
+
+    #[test]
+    fn test_expand_wildcard() {
+        let addresses = expand(&params("10.0.*.1", None, Some("256"))).unwrap();
+        assert_eq!(256, addresses.len());
+        assert_eq!("10.0.255.1", addresses[255]);
+        let cidrs = expand(&params("10.*.0.*", Some("cidrs"), Some("256"))).unwrap();
+        assert_eq!(strings(&["10.0.0.0/24", "10.1.0.0/24"]), cidrs[..2]);
+        assert_eq!(256, cidrs.len());
+        assert_eq!(
+            strings(&["0.0.0.0/0"]),
+            expand(&params("*.*.*.*", Some("cidrs"), None)).unwrap()
+        );
+        assert_eq!(
+            "invalid_address",
+            expand(&params("10.*.1", None, None)).unwrap_err().code()
+        );
+    }
+
+    #[test]
+    fn test_expand_limit() {
+        let err = expand(&params("10.0.*.1", None, Some("255"))).unwrap_err();
+        assert_eq!("too_large", err.code());
+        let err = expand(&params("*.*.*.1", Some("cidrs"), None)).unwrap_err();
+        assert_eq!("too_large", err.code());
+        let err = expand(&params("2001:db8::/64", None, None)).unwrap_err();
+        assert_eq!("too_large", err.code());
+        let err = expand(&params("10.0.0.0/8", None, Some("0"))).unwrap_err();
+        assert_eq!("out_of_range", err.code());
+    }
+
+    #[test]
+    fn test_compress() {
+        let values = strings(&[
+            "10.0.0.5",
+            "10.0.0.1-10.0.0.4",
+            "10.0.0.6/31",
+            "10.0.1.*",
+            "192.0.2.1",
+            "2001:db8::1",
+            "2001:db8::/128",
+        ]);
+        assert_eq!(
+            strings(&[
+                "10.0.0.1-10.0.0.7",
+                "10.0.1.0-10.0.1.255",
+                "192.0.2.1",
+                "2001:db8::-2001:db8::1"
+            ]),
+            compress(&values, "ranges").unwrap()
+        );
+        assert_eq!(
+            strings(&[
+                "10.0.0.1/32",
+                "10.0.0.2/31",
+                "10.0.0.4/30",
+                "10.0.1.0/24",
+                "192.0.2.1/32",
+                "2001:db8::/127"
+            ]),
+            compress(&values, "cidrs").unwrap()
+        );
+        assert_eq!(
+            "invalid_address",
+            compress(&strings(&["nope"]), "ranges").unwrap_err().code()
+        );
+    }
+
+    #[test]
+    fn test_compress_limit() {
+        // Each wildcard fits on its own, but not all of them together.
+        let values = vec!["10.0.*.1".to_owned(); 300];
+        assert_eq!(
+            Err(RangeError::TooLarge {
+                param: "addresses",
+                value: "10.0.*.1".to_owned(),
+                limit: MAX_LIMIT,
+            }),
+            compress(&values, "ranges")
+        );
+        assert_eq!(
+            strings(&["10.0.0.1"]),
+            compress(&vec!["10.0.0.1".to_owned(); 300], "ranges").unwrap()
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use axum::{response::IntoResponse, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{calc_ip, BatchError, IPParams, IpOpsError, MAX_BATCH_JOBS};
+
+#[derive(Debug, Deserialize)]
+pub struct KnownPair {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecoverRequest {
+    pairs: Vec<KnownPair>,
+    op: Option<String>,
+    mode: Option<String>,
+    cipher: Option<String>,
+    #[serde(rename = "in")]
+    input: Option<String>,
+    #[serde(rename = "out")]
+    output: Option<String>,
+}
+
+/// Derives the key of every known `from`/`to` pair and checks that they all
+/// agree. The key derived by most pairs (the earliest one on a tie) is taken
+/// as the recovered key; every pair that disagrees with it or cannot be
+/// derived at all is reported as inconsistent.
+fn recover(recover_request: &RecoverRequest) -> Result<Value, BatchError> {
+    if recover_request.pairs.is_empty() {
+        return Err(IpOpsError::MissingOperand { params: &["pairs"] }.into());
+    }
+    if recover_request.pairs.len() > MAX_BATCH_JOBS {
+        return Err(BatchError::InvalidBatch {
+            message: format!("at most {} pairs are allowed", MAX_BATCH_JOBS),
+        });
+    }
+
+    let keys: Vec<Result<String, IpOpsError>> = recover_request
+        .pairs
+        .iter()
+        .map(|pair| {
+            calc_ip(&IPParams {
+                from: Some(pair.from.clone()),
+                to: Some(pair.to.clone()),
+                op: recover_request.op.clone(),
+                mode: recover_request.mode.clone(),
+                cipher: recover_request.cipher.clone(),
+                input: recover_request.input.clone(),
+                output: recover_request.output.clone(),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    // Number of pairs that derived each key, and the first of them.
+    let mut votes: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (index, key) in keys.iter().enumerate() {
+        if let Ok(key) = key {
+            votes.entry(key).or_insert((0, index)).0 += 1;
+        }
+    }
+    let recovered = votes
+        .into_iter()
+        .max_by_key(|(_, (count, first))| (*count, Reverse(*first)))
+        .map(|(key, _)| key);
+
+    let mut inconsistent = vec![];
+    let pairs: Vec<Value> = recover_request
+        .pairs
+        .iter()
+        .zip(&keys)
+        .enumerate()
+        .map(|(index, (pair, key))| {
+            let mut report = json!({ "index": index, "from": pair.from, "to": pair.to });
+            match key {
+                Ok(key) => {
+                    let consistent = Some(key.as_str()) == recovered;
+                    report["key"] = json!(key);
+                    report["consistent"] = json!(consistent);
+                    if !consistent {
+                        inconsistent.push(index);
+                    }
+                }
+                Err(e) => {
+                    report["error"] = e.to_json();
+                    report["consistent"] = json!(false);
+                    inconsistent.push(index);
+                }
+            }
+            report
+        })
+        .collect();
+
+    Ok(json!({
+        "key": recovered,
+        "consistent": inconsistent.is_empty(),
+        "inconsistent": inconsistent,
+        "pairs": pairs,
+    }))
+}
+
+pub async fn recover_key(
+    Json(recover_request): Json<RecoverRequest>,
+) -> Result<impl IntoResponse, BatchError> {
+    Ok(Json(recover(&recover_request)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(pairs: &[(&str, &str)], op: Option<&str>) -> RecoverRequest {
+        RecoverRequest {
+            pairs: pairs
+                .iter()
+                .map(|(from, to)| KnownPair {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                })
+                .collect(),
+            op: op.map(str::to_owned),
+            mode: None,
+            cipher: None,
+            input: None,
+            output: None,
+        }
+    }
+
+    #[test]
+    fn test_recover_consistent() {
+        let report = recover(&request(
+            &[("10.0.0.0", "11.2.3.255"), ("192.168.0.1", "193.170.3.0")],
+            None,
+        ))
+        .unwrap();
+        assert_eq!("1.2.3.255", report["key"]);
+        assert_eq!(true, report["consistent"]);
+
+        let report = recover(&request(
+            &[("fe80::1", "fe85:6:7::3332"), ("::", "5:6:7::3333")],
+            None,
+        ))
+        .unwrap();
+        assert_eq!("5:6:7::3333", report["key"]);
+    }
+
+    #[test]
+    fn test_recover_inconsistent() {
+        let report = recover(&request(
+            &[
+                ("10.0.0.1", "10.0.0.3"),
+                ("10.0.0.2", "10.0.0.1"),
+                ("10.0.0.5", "10.0.0.7"),
+                ("10.0.0.5", "::1"),
+            ],
+            Some("xor"),
+        ))
+        .unwrap();
+        assert_eq!("0.0.0.2", report["key"]);
+        assert_eq!(false, report["consistent"]);
+        assert_eq!(json!([1, 3]), report["inconsistent"]);
+        assert_eq!("0.0.0.3", report["pairs"][1]["key"]);
+        assert_eq!("family_mismatch", report["pairs"][3]["error"]["error"]);
+
+        // On a tie, the key of the earliest pair wins.
+        let report = recover(&request(
+            &[("10.0.0.2", "10.0.0.3"), ("10.0.0.1", "10.0.0.3")],
+            Some("xor"),
+        ))
+        .unwrap();
+        assert_eq!("0.0.0.1", report["key"]);
+    }
+
+    #[test]
+    fn test_recover_empty() {
+        assert_eq!(
+            "missing_operand",
+            recover(&request(&[], None)).unwrap_err().code()
+        );
+    }
+}
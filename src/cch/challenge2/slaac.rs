@@ -0,0 +1,456 @@
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    net::{IpAddr, Ipv6Addr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use ipnet::{IpNet, Ipv6Net};
+use ring::hmac;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{
+    inspect::{eui64_mac, format_mac},
+    subnet::{parse_number, parse_prefix},
+    IpOpsError,
+};
+
+/// Interface identifiers are the low 64 bits, so SLAAC prefixes are /64 or shorter.
+const MAX_PREFIX_LEN: u8 = 64;
+/// RFC 7217 gives up after a few collisions; so do we with reserved IIDs.
+const MAX_DAD_COUNTER: u128 = 255;
+
+/// The RFC 7217 `secret_key`.
+const SECRET_VAR: &str = "CCH_SLAAC_SECRET";
+/// A file holding the secret instead, read when `CCH_SLAAC_SECRET` is unset.
+const SECRET_FILE_VAR: &str = "CCH_SLAAC_SECRET_FILE";
+
+/// Everything that can go wrong on the `/2/v6` SLAAC routes. Bad parameters
+/// are `400 Bad Request`s, while a server without a secret answers
+/// `/2/v6/stable` with a `503 Service Unavailable`.
+#[derive(Debug, PartialEq)]
+pub enum SlaacError {
+    InvalidMac { param: &'static str, value: String },
+    NotEui64 { addr: Ipv6Addr },
+    SecretUnavailable,
+    Params(IpOpsError),
+}
+impl SlaacError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidMac { .. } => "invalid_mac",
+            Self::NotEui64 { .. } => "not_eui64",
+            Self::SecretUnavailable => "secret_unavailable",
+            Self::Params(e) => e.code(),
+        }
+    }
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::SecretUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+    fn to_json(&self) -> Value {
+        let mut body = json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        });
+        match self {
+            Self::InvalidMac { param, value } => {
+                body["param"] = json!(param);
+                body["value"] = json!(value);
+            }
+            Self::NotEui64 { addr } => body["addr"] = json!(addr.to_string()),
+            Self::SecretUnavailable => {}
+            Self::Params(e) => body = e.to_json(),
+        }
+        body
+    }
+}
+impl From<IpOpsError> for SlaacError {
+    fn from(e: IpOpsError) -> Self {
+        Self::Params(e)
+    }
+}
+impl Display for SlaacError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidMac { param, value } => {
+                write!(f, "`{}` is not a valid MAC address: {:?}", param, value)
+            }
+            Self::NotEui64 { addr } => {
+                write!(f, "{} does not have an EUI-64 interface identifier", addr)
+            }
+            Self::SecretUnavailable => write!(
+                f,
+                "no secret is configured for stable addresses, see {} or {}",
+                SECRET_VAR, SECRET_FILE_VAR
+            ),
+            Self::Params(e) => e.fmt(f),
+        }
+    }
+}
+impl IntoResponse for SlaacError {
+    fn into_response(self) -> Response {
+        (self.status_code(), Json(self.to_json())).into_response()
+    }
+}
+
+/// The server-wide RFC 7217 secret. It is configuration rather than a request
+/// parameter: whoever knows it can tell which addresses belong to one host.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StableSecret {
+    secret: Option<Vec<u8>>,
+}
+impl StableSecret {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: Some(secret.to_vec()).filter(|secret| !secret.is_empty()),
+        }
+    }
+
+    /// Reads the secret from `CCH_SLAAC_SECRET`, or from the file named by
+    /// `CCH_SLAAC_SECRET_FILE` without its trailing newline. An unreadable
+    /// file is reported and leaves stable addresses unavailable.
+    pub fn shared_from_env() -> Arc<Self> {
+        if let Ok(secret) = env::var(SECRET_VAR) {
+            return Arc::new(Self::new(secret.as_bytes()));
+        }
+        let Ok(path) = env::var(SECRET_FILE_VAR) else {
+            return Arc::new(Self::default());
+        };
+        match fs::read(&path) {
+            Ok(secret) => {
+                let len = secret.trim_ascii_end().len();
+                Arc::new(Self::new(&secret[..len]))
+            }
+            Err(e) => {
+                tracing::warn!("ignoring {} ({}): {}", SECRET_FILE_VAR, path, e);
+                Arc::new(Self::default())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SlaacParams {
+    prefix: Option<String>,
+    mac: Option<String>,
+    addr: Option<String>,
+    iface: Option<String>,
+    network_id: Option<String>,
+    dad_counter: Option<String>,
+}
+
+fn required<'a>(
+    value: &'a Option<String>,
+    params: &'static [&'static str],
+) -> Result<&'a str, IpOpsError> {
+    value
+        .as_deref()
+        .ok_or(IpOpsError::MissingOperand { params })
+}
+
+/// Reads `00:11:22:33:44:55`, `00-11-22-33-44-55`, `0011.2233.4455` or bare hex.
+fn parse_mac(value: &str) -> Result<[u8; 6], SlaacError> {
+    let digits: String = value
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-' | '.'))
+        .collect();
+    let mut mac = [0u8; 6];
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(SlaacError::InvalidMac {
+            param: "mac",
+            value: value.to_owned(),
+        });
+    }
+    for (i, octet) in mac.iter_mut().enumerate() {
+        *octet = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).map_err(|_| {
+            SlaacError::InvalidMac {
+                param: "mac",
+                value: value.to_owned(),
+            }
+        })?;
+    }
+    Ok(mac)
+}
+
+fn slaac_prefix(value: &str) -> Result<Ipv6Net, IpOpsError> {
+    match parse_prefix("prefix", value)? {
+        IpNet::V6(net) if net.prefix_len() <= MAX_PREFIX_LEN => Ok(net),
+        IpNet::V6(net) => Err(IpOpsError::OutOfRange {
+            param: "prefix",
+            value: net.prefix_len().to_string(),
+            min: 0,
+            max: MAX_PREFIX_LEN as u128,
+        }),
+        IpNet::V4(_) => Err(IpOpsError::InvalidAddress {
+            param: "prefix",
+            value: value.to_owned(),
+        }),
+    }
+}
+
+fn with_iid(prefix: Ipv6Net, iid: u64) -> Ipv6Addr {
+    Ipv6Addr::from(u128::from(prefix.network()) | iid as u128)
+}
+
+/// The modified EUI-64 identifier of RFC 4291: `ff:fe` in the middle and the
+/// universal/local bit flipped.
+fn eui64_iid(mac: [u8; 6]) -> u64 {
+    u64::from_be_bytes([
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ])
+}
+
+/// Identifiers RFC 5453 sets aside, which must never be generated.
+fn is_reserved_iid(iid: u64) -> bool {
+    iid == 0
+        || (0x0200_5eff_fe00_0000..=0x0200_5eff_feff_ffff).contains(&iid)
+        || iid >= 0xfdff_ffff_ffff_ff80
+}
+
+/// RFC 7217 `F(Prefix, Net_Iface, Network_ID, DAD_Counter, secret_key)`,
+/// with HMAC-SHA256 as the pseudorandom function and its first 64 bits as
+/// the identifier.
+fn stable_iid(
+    prefix: Ipv6Net,
+    iface: &str,
+    network_id: &str,
+    dad_counter: u8,
+    secret: &[u8],
+) -> u64 {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let mut context = hmac::Context::with_key(&key);
+    context.update(&prefix.network().octets()[..8]);
+    context.update(&[prefix.prefix_len()]);
+    context.update(iface.as_bytes());
+    context.update(&[0]);
+    context.update(network_id.as_bytes());
+    context.update(&[0, dad_counter]);
+    let tag = context.sign();
+    let mut iid = [0u8; 8];
+    iid.copy_from_slice(&tag.as_ref()[..8]);
+    u64::from_be_bytes(iid)
+}
+
+fn eui64(slaac_params: &SlaacParams) -> Result<Value, SlaacError> {
+    let prefix = slaac_prefix(required(&slaac_params.prefix, &["prefix"])?)?;
+    let mac = parse_mac(required(&slaac_params.mac, &["mac"])?)?;
+    let iid = eui64_iid(mac);
+    Ok(json!({
+        "addr": with_iid(prefix, iid).to_string(),
+        "prefix": prefix.to_string(),
+        "mac": format_mac(mac),
+        "interface_id": with_iid(Ipv6Net::default(), iid).to_string(),
+    }))
+}
+
+fn mac(slaac_params: &SlaacParams) -> Result<Value, SlaacError> {
+    let value = required(&slaac_params.addr, &["addr"])?;
+    let addr = match value.parse::<IpAddr>() {
+        Ok(IpAddr::V6(addr)) => addr,
+        _ => {
+            return Err(IpOpsError::InvalidAddress {
+                param: "addr",
+                value: value.to_owned(),
+            }
+            .into())
+        }
+    };
+    let mac = eui64_mac(addr).ok_or(SlaacError::NotEui64 { addr })?;
+    Ok(json!({
+        "addr": addr.to_string(),
+        "mac": format_mac(mac),
+        "universal": mac[0] & 0x02 == 0,
+        "multicast": mac[0] & 0x01 != 0,
+    }))
+}
+
+fn stable(slaac_params: &SlaacParams, secret: &StableSecret) -> Result<Value, SlaacError> {
+    let secret = secret
+        .secret
+        .as_deref()
+        .ok_or(SlaacError::SecretUnavailable)?;
+    let prefix = slaac_prefix(required(&slaac_params.prefix, &["prefix"])?)?;
+    let iface = required(&slaac_params.iface, &["iface"])?;
+    let network_id = slaac_params.network_id.as_deref().unwrap_or_default();
+    let first_counter = match slaac_params.dad_counter.as_deref() {
+        Some(dad_counter) => parse_number("dad_counter", dad_counter, 0, MAX_DAD_COUNTER)?,
+        None => 0,
+    };
+
+    // A reserved identifier counts as a collision: bump the counter and retry.
+    for dad_counter in first_counter..=MAX_DAD_COUNTER {
+        let iid = stable_iid(prefix, iface, network_id, dad_counter as u8, secret);
+        if !is_reserved_iid(iid) {
+            return Ok(json!({
+                "addr": with_iid(prefix, iid).to_string(),
+                "prefix": prefix.to_string(),
+                "interface_id": with_iid(Ipv6Net::default(), iid).to_string(),
+                "dad_counter": dad_counter,
+            }));
+        }
+    }
+    Err(IpOpsError::OutOfRange {
+        param: "dad_counter",
+        value: first_counter.to_string(),
+        min: 0,
+        max: MAX_DAD_COUNTER,
+    }
+    .into())
+}
+
+/// The SLAAC address a host with `mac` picks in `prefix`.
+pub async fn eui64_addr(slaac_params: Query<SlaacParams>) -> Result<impl IntoResponse, SlaacError> {
+    Ok(Json(eui64(&slaac_params.0)?))
+}
+
+/// The MAC address embedded in the EUI-64 identifier of `addr`.
+pub async fn eui64_to_mac(
+    slaac_params: Query<SlaacParams>,
+) -> Result<impl IntoResponse, SlaacError> {
+    Ok(Json(mac(&slaac_params.0)?))
+}
+
+/// An RFC 7217 stable, opaque address for `iface` in `prefix`, under the
+/// secret the server was configured with.
+pub async fn stable_addr(
+    slaac_params: Query<SlaacParams>,
+    Extension(secret): Extension<Arc<StableSecret>>,
+) -> Result<impl IntoResponse, SlaacError> {
+    Ok(Json(stable(&slaac_params.0, &secret)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> SlaacParams {
+        let mut slaac_params = SlaacParams::default();
+        for (key, value) in pairs {
+            let value = Some(value.to_string());
+            match *key {
+                "prefix" => slaac_params.prefix = value,
+                "mac" => slaac_params.mac = value,
+                "addr" => slaac_params.addr = value,
+                "iface" => slaac_params.iface = value,
+                "network_id" => slaac_params.network_id = value,
+                "dad_counter" => slaac_params.dad_counter = value,
+                _ => unreachable!(),
+            }
+        }
+        slaac_params
+    }
+
+    #[test]
+    fn test_eui64() {
+        let report = eui64(&params(&[
+            ("prefix", "2001:db8:1:2::/64"),
+            ("mac", "00-1A-2B-3C-4D-5E"),
+        ]))
+        .unwrap();
+        assert_eq!("2001:db8:1:2:21a:2bff:fe3c:4d5e", report["addr"]);
+        assert_eq!("::21a:2bff:fe3c:4d5e", report["interface_id"]);
+        assert_eq!("00:1a:2b:3c:4d:5e", report["mac"]);
+        let report = eui64(&params(&[
+            ("prefix", "fe80::/10"),
+            ("mac", "021a.2b3c.4d5e"),
+        ]))
+        .unwrap();
+        assert_eq!("fe80::1a:2bff:fe3c:4d5e", report["addr"]);
+
+        let err = eui64(&params(&[
+            ("prefix", "2001:db8::/80"),
+            ("mac", "001a2b3c4d5e"),
+        ]))
+        .unwrap_err();
+        assert_eq!("out_of_range", err.code());
+        let err = eui64(&params(&[
+            ("prefix", "2001:db8::/64"),
+            ("mac", "00:1a:2b:3c:4d"),
+        ]))
+        .unwrap_err();
+        assert_eq!("invalid_mac", err.code());
+        assert_eq!("invalid_mac", parse_mac("+a+b+c+d+e+f").unwrap_err().code());
+        let err = eui64(&params(&[
+            ("prefix", "10.0.0.0/8"),
+            ("mac", "001a2b3c4d5e"),
+        ]))
+        .unwrap_err();
+        assert_eq!("invalid_address", err.code());
+    }
+
+    #[test]
+    fn test_mac() {
+        let report = mac(&params(&[("addr", "2001:db8:1:2:21a:2bff:fe3c:4d5e")])).unwrap();
+        assert_eq!("00:1a:2b:3c:4d:5e", report["mac"]);
+        assert_eq!(true, report["universal"]);
+        let err = mac(&params(&[("addr", "2001:db8::1")])).unwrap_err();
+        assert_eq!("not_eui64", err.code());
+        assert_eq!(
+            "missing_operand",
+            mac(&SlaacParams::default()).unwrap_err().code()
+        );
+    }
+
+    #[test]
+    fn test_stable() {
+        let secret = StableSecret::new(b"s3cr3t");
+        let base = [("prefix", "2001:db8:1:2::/64"), ("iface", "eth0")];
+        let first = stable(&params(&base), &secret).unwrap();
+        assert_eq!(first, stable(&params(&base), &secret).unwrap());
+        assert_eq!(0, first["dad_counter"]);
+        let addr: Ipv6Addr = first["addr"].as_str().unwrap().parse().unwrap();
+        assert_eq!(0x2001_0db8_0001_0002, u128::from(addr) >> 64);
+
+        let mut other = base.to_vec();
+        other.push(("dad_counter", "1"));
+        assert_ne!(
+            first["addr"],
+            stable(&params(&other), &secret).unwrap()["addr"]
+        );
+        let another = StableSecret::new(b"another secret");
+        assert_ne!(
+            first["addr"],
+            stable(&params(&base), &another).unwrap()["addr"]
+        );
+        let mut other = base.to_vec();
+        other[0] = ("prefix", "2001:db8:1:3::/64");
+        assert_ne!(
+            first["interface_id"],
+            stable(&params(&other), &secret).unwrap()["interface_id"]
+        );
+        assert_eq!(
+            "missing_operand",
+            stable(&params(&base[..1]), &secret).unwrap_err().code()
+        );
+        let err = stable(&params(&base), &StableSecret::new(b"")).unwrap_err();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, err.status_code());
+    }
+
+    #[test]
+    fn test_reserved_iids() {
+        assert!(is_reserved_iid(0));
+        assert!(is_reserved_iid(0x0200_5eff_fe00_5213));
+        assert!(is_reserved_iid(0xfdff_ffff_ffff_ffff));
+        assert!(!is_reserved_iid(eui64_iid([
+            0, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e
+        ])));
+    }
+}
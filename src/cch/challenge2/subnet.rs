@@ -0,0 +1,359 @@
+use std::{fmt::Display, net::IpAddr};
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::IpOpsError;
+
+/// Largest number of subnets a single split may return.
+const MAX_SUBNETS: u128 = 4096;
+
+/// `2^128`, the size of `::/0`, which does not fit in a `u128`.
+pub(super) const IPV6_ADDRESS_SPACE: &str = "340282366920938463463374607431768211456";
+
+/// A split that would return more than [`MAX_SUBNETS`] subnets is refused
+/// with its own code, so that clients can tell it from a prefix that is out of
+/// range for the address family. Every other error is an [`IpOpsError`].
+#[derive(Debug, PartialEq)]
+pub enum SubnetError {
+    TooManySubnets { requested: String, limit: u128 },
+    Params(IpOpsError),
+}
+impl SubnetError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::TooManySubnets { .. } => "too_many_subnets",
+            Self::Params(e) => e.code(),
+        }
+    }
+    fn to_json(&self) -> Value {
+        match self {
+            Self::TooManySubnets { requested, limit } => json!({
+                "error": self.code(),
+                "message": self.to_string(),
+                "requested": requested,
+                "limit": limit.to_string(),
+            }),
+            Self::Params(e) => e.to_json(),
+        }
+    }
+}
+impl From<IpOpsError> for SubnetError {
+    fn from(e: IpOpsError) -> Self {
+        Self::Params(e)
+    }
+}
+impl Display for SubnetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooManySubnets { requested, limit } => write!(
+                f,
+                "the split would return {} subnets, at most {} are allowed",
+                requested, limit
+            ),
+            Self::Params(e) => e.fmt(f),
+        }
+    }
+}
+impl IntoResponse for SubnetError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, Json(self.to_json())).into_response()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SubnetParams {
+    prefix: Option<String>,
+    count: Option<String>,
+    new_prefix: Option<String>,
+}
+
+pub(super) fn parse_prefix(param: &'static str, value: &str) -> Result<IpNet, IpOpsError> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map(|net| net.trunc())
+        .map_err(|_| IpOpsError::InvalidAddress {
+            param,
+            value: value.to_owned(),
+        })
+}
+
+pub(super) fn parse_number(
+    param: &'static str,
+    value: &str,
+    min: u128,
+    max: u128,
+) -> Result<u128, IpOpsError> {
+    value
+        .parse::<u128>()
+        .ok()
+        .filter(|number| (min..=max).contains(number))
+        .ok_or(IpOpsError::OutOfRange {
+            param,
+            value: value.to_owned(),
+            min,
+            max,
+        })
+}
+
+fn required_prefix(subnet_params: &SubnetParams) -> Result<IpNet, IpOpsError> {
+    let prefix = subnet_params
+        .prefix
+        .as_deref()
+        .ok_or(IpOpsError::MissingOperand {
+            params: &["prefix"],
+        })?;
+    parse_prefix("prefix", prefix)
+}
+
+/// Number of addresses in a block of `host_bits` host bits, as a decimal
+/// string since it overflows every integer type for `::/0`.
+fn address_count(host_bits: u8) -> String {
+    1u128
+        .checked_shl(host_bits as u32)
+        .map(|count| count.to_string())
+        .unwrap_or_else(|| IPV6_ADDRESS_SPACE.to_owned())
+}
+
+fn describe(net: IpNet) -> Value {
+    let host_bits = net.max_prefix_len() - net.prefix_len();
+    let mut hosts = net.hosts();
+    let first_host = hosts.next();
+    let last_host = hosts.next_back().or(first_host);
+    // IPv4 networks lose their network and broadcast addresses, except for
+    // point-to-point /31 links (RFC 3021) and single-host /32s.
+    let host_count = match net {
+        IpNet::V4(_) if host_bits >= 2 => ((1u128 << host_bits) - 2).to_string(),
+        _ => address_count(host_bits),
+    };
+    let (family, broadcast) = match net {
+        IpNet::V4(net) => ("ipv4", json!(net.broadcast().to_string())),
+        IpNet::V6(_) => ("ipv6", Value::Null),
+    };
+
+    json!({
+        "prefix": net.to_string(),
+        "family": family,
+        "prefix_len": net.prefix_len(),
+        "network": net.network().to_string(),
+        "broadcast": broadcast,
+        "netmask": net.netmask().to_string(),
+        "hostmask": net.hostmask().to_string(),
+        "first_host": first_host.map(|addr| addr.to_string()),
+        "last_host": last_host.map(|addr| addr.to_string()),
+        "host_count": host_count,
+        "address_count": address_count(host_bits),
+    })
+}
+
+fn split(subnet_params: &SubnetParams) -> Result<Vec<IpNet>, SubnetError> {
+    let net = required_prefix(subnet_params)?;
+    let max_prefix_len = net.max_prefix_len() as u128;
+    let (new_prefix_len, count) = match (&subnet_params.count, &subnet_params.new_prefix) {
+        (Some(count), None) => {
+            let count = parse_number("count", count, 1, u128::MAX)?;
+            if count > MAX_SUBNETS {
+                return Err(SubnetError::TooManySubnets {
+                    requested: count.to_string(),
+                    limit: MAX_SUBNETS,
+                });
+            }
+            // Smallest prefix length that yields at least `count` subnets.
+            let extra_bits = count.next_power_of_two().trailing_zeros() as u128;
+            let new_prefix_len = net.prefix_len() as u128 + extra_bits;
+            if new_prefix_len > max_prefix_len {
+                return Err(IpOpsError::OutOfRange {
+                    param: "count",
+                    value: count.to_string(),
+                    min: 1,
+                    max: 1 << (max_prefix_len - net.prefix_len() as u128),
+                }
+                .into());
+            }
+            (new_prefix_len as u8, count as usize)
+        }
+        (None, Some(new_prefix)) => {
+            let min = net.prefix_len() as u128;
+            let new_prefix_len = parse_number("new_prefix", new_prefix, min, max_prefix_len)?;
+            let extra_bits = new_prefix_len - min;
+            if extra_bits > MAX_SUBNETS.trailing_zeros() as u128 {
+                return Err(SubnetError::TooManySubnets {
+                    requested: address_count(extra_bits as u8),
+                    limit: MAX_SUBNETS,
+                });
+            }
+            (new_prefix_len as u8, 1 << extra_bits)
+        }
+        (Some(_), Some(_)) => {
+            return Err(IpOpsError::ConflictingOperands {
+                params: &["count", "new_prefix"],
+            }
+            .into())
+        }
+        (None, None) => {
+            return Err(IpOpsError::MissingOperand {
+                params: &["count", "new_prefix"],
+            }
+            .into())
+        }
+    };
+
+    let subnets = net
+        .subnets(new_prefix_len)
+        .map_err(|_| IpOpsError::OutOfRange {
+            param: "new_prefix",
+            value: new_prefix_len.to_string(),
+            min: net.prefix_len() as u128,
+            max: max_prefix_len,
+        })?;
+    Ok(subnets.take(count).collect())
+}
+
+fn aggregate(prefixes: &[String]) -> Result<Vec<IpNet>, IpOpsError> {
+    let networks = prefixes
+        .iter()
+        .map(|prefix| parse_prefix("prefixes", prefix))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(IpNet::aggregate(&networks))
+}
+
+fn to_strings(networks: Vec<IpNet>) -> Vec<String> {
+    networks.into_iter().map(|net| net.to_string()).collect()
+}
+
+pub async fn subnet_info(
+    subnet_params: Query<SubnetParams>,
+) -> Result<impl IntoResponse, IpOpsError> {
+    let net = required_prefix(&subnet_params.0)?;
+    Ok(Json(describe(net)))
+}
+
+/// Splits `prefix` into `count` equally sized subnets (the first `count` of
+/// the next power of two), or into every subnet of length `new_prefix`, as
+/// long as that is at most [`MAX_SUBNETS`] of them.
+pub async fn split_subnet(
+    subnet_params: Query<SubnetParams>,
+) -> Result<impl IntoResponse, SubnetError> {
+    let subnets = split(&subnet_params.0)?;
+    Ok(Json(json!({ "subnets": to_strings(subnets) })))
+}
+
+/// Merges a JSON array of prefixes into the smallest set of covering prefixes.
+pub async fn aggregate_subnets(
+    Json(prefixes): Json<Vec<String>>,
+) -> Result<impl IntoResponse, IpOpsError> {
+    let networks = aggregate(&prefixes)?;
+    Ok(Json(json!({ "prefixes": to_strings(networks) })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(prefix: &str, count: Option<&str>, new_prefix: Option<&str>) -> SubnetParams {
+        SubnetParams {
+            prefix: Some(prefix.to_owned()),
+            count: count.map(str::to_owned),
+            new_prefix: new_prefix.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_info_v4() {
+        let info = describe(parse_prefix("prefix", "192.168.1.77/26").unwrap());
+        assert_eq!("192.168.1.64/26", info["prefix"]);
+        assert_eq!("192.168.1.127", info["broadcast"]);
+        assert_eq!("255.255.255.192", info["netmask"]);
+        assert_eq!("0.0.0.63", info["hostmask"]);
+        assert_eq!("192.168.1.65", info["first_host"]);
+        assert_eq!("192.168.1.126", info["last_host"]);
+        assert_eq!("62", info["host_count"]);
+
+        let info = describe(parse_prefix("prefix", "10.0.0.0/31").unwrap());
+        assert_eq!("10.0.0.0", info["first_host"]);
+        assert_eq!("10.0.0.1", info["last_host"]);
+        assert_eq!("2", info["host_count"]);
+    }
+
+    #[test]
+    fn test_info_v6() {
+        let info = describe(parse_prefix("prefix", "2001:db8::1/64").unwrap());
+        assert_eq!("2001:db8::/64", info["prefix"]);
+        assert_eq!(Value::Null, info["broadcast"]);
+        assert_eq!("2001:db8::ffff:ffff:ffff:ffff", info["last_host"]);
+        assert_eq!("18446744073709551616", info["host_count"]);
+        let info = describe(parse_prefix("prefix", "::/0").unwrap());
+        assert_eq!(IPV6_ADDRESS_SPACE, info["address_count"]);
+    }
+
+    #[test]
+    fn test_split() {
+        let subnets = split(&params("10.0.0.0/24", Some("3"), None)).unwrap();
+        assert_eq!(
+            vec!["10.0.0.0/26", "10.0.0.64/26", "10.0.0.128/26"],
+            to_strings(subnets)
+        );
+        let subnets = split(&params("2001:db8::/32", None, Some("34"))).unwrap();
+        assert_eq!(4, subnets.len());
+        assert_eq!(
+            "out_of_range",
+            split(&params("10.0.0.0/31", Some("4"), None))
+                .unwrap_err()
+                .code()
+        );
+        assert_eq!(
+            "out_of_range",
+            split(&params("10.0.0.0/8", None, Some("33")))
+                .unwrap_err()
+                .code()
+        );
+        assert_eq!(
+            Err(SubnetError::TooManySubnets {
+                requested: "65536".to_owned(),
+                limit: MAX_SUBNETS,
+            }),
+            split(&params("10.0.0.0/8", None, Some("24")))
+        );
+        assert_eq!(
+            "too_many_subnets",
+            split(&params("10.0.0.0/8", Some("5000"), None))
+                .unwrap_err()
+                .code()
+        );
+        assert_eq!(
+            "conflicting_operands",
+            split(&params("10.0.0.0/8", Some("2"), Some("9")))
+                .unwrap_err()
+                .code()
+        );
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let prefixes = [
+            "10.0.0.0/25",
+            "10.0.0.128/25",
+            "10.0.1.0/24",
+            "10.0.0.5",
+            "::1",
+            "::/128",
+        ]
+        .map(str::to_owned);
+        assert_eq!(
+            vec!["10.0.0.0/23", "::/127"],
+            to_strings(aggregate(&prefixes).unwrap())
+        );
+        assert_eq!(
+            "invalid_address",
+            aggregate(&["nope".to_owned()]).unwrap_err().code()
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use axum::http::{header::ACCEPT, HeaderMap};
+use serde_json::{json, Value};
+
+use super::order::Order;
+
+pub const CSV_MIME_TYPE: &str = "text/csv";
+const NORMALIZATIONS: &[&str] = &["case", "whitespace"];
+const SORT_KEYS: &[&str] = &["input", "name", "quantity"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum SortBy {
+    /// Where each item first appeared.
+    #[default]
+    Input,
+    /// Alphabetically.
+    Name,
+    /// Largest quantity first.
+    Quantity,
+}
+
+/// How duplicate items are recognised and the merged lines ordered.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Aggregation {
+    fold_case: bool,
+    fold_whitespace: bool,
+    sort: SortBy,
+}
+impl Aggregation {
+    /// Reads `normalize` (a comma-separated list of `case` and `whitespace`)
+    /// and `sort` (`input`, `name` or `quantity`).
+    pub fn from_params(normalize: Option<&str>, sort: Option<&str>) -> Result<Self, String> {
+        let mut aggregation = Self::default();
+        for normalization in normalize.unwrap_or_default().split(',').map(str::trim) {
+            match normalization {
+                "" => {}
+                "case" => aggregation.fold_case = true,
+                "whitespace" => aggregation.fold_whitespace = true,
+                _ => {
+                    return Err(format!(
+                        "Invalid normalize, expected any of: {}",
+                        NORMALIZATIONS.join(", ")
+                    ))
+                }
+            }
+        }
+        aggregation.sort = match sort.unwrap_or("input") {
+            "input" => SortBy::Input,
+            "name" => SortBy::Name,
+            "quantity" => SortBy::Quantity,
+            _ => {
+                return Err(format!(
+                    "Invalid sort, expected one of: {}",
+                    SORT_KEYS.join(", ")
+                ))
+            }
+        };
+        Ok(aggregation)
+    }
+
+    /// The item as shown: with runs of whitespace collapsed if asked to.
+    fn display_name(&self, item: &str) -> String {
+        match self.fold_whitespace {
+            true => item.split_whitespace().collect::<Vec<_>>().join(" "),
+            false => item.to_owned(),
+        }
+    }
+
+    /// What two items must share to be merged.
+    fn key(&self, item: &str) -> String {
+        let name = self.display_name(item);
+        match self.fold_case {
+            true => name.to_lowercase(),
+            false => name,
+        }
+    }
+
+    /// Merges orders for the same item, keeping the spelling it was first
+    /// ordered under, and sums their quantities.
+    pub fn aggregate(&self, orders: &[Order]) -> Summary {
+        let mut lines: Vec<Line> = vec![];
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for order in orders {
+            let key = self.key(&order.item);
+            match positions.get(&key) {
+                Some(&position) => {
+                    lines[position].quantity += order.quantity as u64;
+                    lines[position].orders += 1;
+                }
+                None => {
+                    positions.insert(key.clone(), lines.len());
+                    lines.push(Line {
+                        item: self.display_name(&order.item),
+                        key,
+                        quantity: order.quantity as u64,
+                        orders: 1,
+                    });
+                }
+            }
+        }
+        match self.sort {
+            SortBy::Input => {}
+            SortBy::Name => lines.sort_by(|a, b| a.key.cmp(&b.key).then(a.item.cmp(&b.item))),
+            SortBy::Quantity => {
+                lines.sort_by(|a, b| b.quantity.cmp(&a.quantity).then(a.key.cmp(&b.key)))
+            }
+        }
+        Summary {
+            lines,
+            order_count: orders.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    item: String,
+    key: String,
+    /// Wide enough that summing many `u32` quantities cannot overflow.
+    quantity: u64,
+    orders: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    lines: Vec<Line>,
+    order_count: usize,
+}
+impl Summary {
+    fn total_quantity(&self) -> u64 {
+        self.lines.iter().map(|line| line.quantity).sum()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for line in &self.lines {
+            text.push_str(&format!("{}: {}\n", line.item, line.quantity));
+        }
+        text.push_str(&format!(
+            "Total: {} across {} items",
+            self.total_quantity(),
+            self.lines.len()
+        ));
+        text
+    }
+
+    pub fn to_json(&self) -> Value {
+        let items: Vec<Value> = self
+            .lines
+            .iter()
+            .map(|line| json!({ "item": line.item, "quantity": line.quantity, "orders": line.orders }))
+            .collect();
+        json!({
+            "items": items,
+            "totals": {
+                "items": self.lines.len(),
+                "orders": self.order_count,
+                "quantity": self.total_quantity(),
+            },
+        })
+    }
+
+    /// RFC 4180 CSV, with the totals as a last `TOTAL` row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("item,quantity,orders\r\n");
+        for line in &self.lines {
+            csv.push_str(&format!(
+                "{},{},{}\r\n",
+                csv_field(&line.item),
+                line.quantity,
+                line.orders
+            ));
+        }
+        csv.push_str(&format!(
+            "TOTAL,{},{}\r\n",
+            self.total_quantity(),
+            self.order_count
+        ));
+        csv
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Text,
+    Json,
+    Csv,
+}
+impl Output {
+    /// The first of JSON, CSV or plain text the `Accept` header names.
+    pub fn from_accept(headers: &HeaderMap) -> Self {
+        headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|range| {
+                range
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_ascii_lowercase()
+            })
+            .find_map(|essence| match essence.as_str() {
+                CSV_MIME_TYPE => Some(Self::Csv),
+                "text/plain" => Some(Self::Text),
+                essence if essence == super::JSON_MIME_TYPE || essence.ends_with("+json") => {
+                    Some(Self::Json)
+                }
+                _ => None,
+            })
+            .unwrap_or(Self::Text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn orders(orders: &[(&str, u32)]) -> Vec<Order> {
+        orders
+            .iter()
+            .map(|(item, quantity)| Order {
+                item: item.to_string(),
+                quantity: *quantity,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_and_normalize() {
+        let orders = orders(&[
+            ("Toy car", 2),
+            ("Lego  brick", 23),
+            ("toy car", 3),
+            (" Lego brick", 1),
+        ]);
+        let exact = Aggregation::from_params(None, None)
+            .unwrap()
+            .aggregate(&orders);
+        assert_eq!(4, exact.lines.len());
+
+        let folded = Aggregation::from_params(Some("case, whitespace"), None)
+            .unwrap()
+            .aggregate(&orders);
+        assert_eq!(
+            "Toy car: 5\nLego brick: 24\nTotal: 29 across 2 items",
+            folded.to_text()
+        );
+        assert_eq!(2, folded.to_json()["items"][0]["orders"]);
+        assert_eq!(4, folded.to_json()["totals"]["orders"]);
+
+        let summary = Aggregation::from_params(Some("whitespace"), None)
+            .unwrap()
+            .aggregate(&orders);
+        assert_eq!(3, summary.lines.len());
+        assert!(Aggregation::from_params(Some("accents"), None).is_err());
+    }
+
+    #[test]
+    fn test_sort() {
+        let orders = orders(&[("b", 1), ("C", 5), ("a", 5)]);
+        let by_name = Aggregation::from_params(Some("case"), Some("name"))
+            .unwrap()
+            .aggregate(&orders);
+        assert_eq!(
+            "a: 5\nb: 1\nC: 5\nTotal: 11 across 3 items",
+            by_name.to_text()
+        );
+        let by_quantity = Aggregation::from_params(None, Some("quantity"))
+            .unwrap()
+            .aggregate(&orders);
+        assert_eq!(
+            vec!["C", "a", "b"],
+            by_quantity
+                .lines
+                .iter()
+                .map(|line| line.item.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(Aggregation::from_params(None, Some("price")).is_err());
+    }
+
+    #[test]
+    fn test_csv_and_totals() {
+        let summary = Aggregation::default().aggregate(&orders(&[
+            ("Sock, \"wool\"", u32::MAX),
+            ("Sock, \"wool\"", u32::MAX),
+        ]));
+        assert_eq!(
+            "item,quantity,orders\r\n\"Sock, \"\"wool\"\"\",8589934590,2\r\nTOTAL,8589934590,2\r\n",
+            summary.to_csv()
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/csv, application/json"),
+        );
+        assert_eq!(Output::Csv, Output::from_accept(&headers));
+        assert_eq!(Output::Text, Output::from_accept(&HeaderMap::new()));
+    }
+}
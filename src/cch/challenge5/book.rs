@@ -0,0 +1,664 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{
+    catalog::{self, Catalog, Shortage},
+    order::{self, Order},
+};
+
+/// Path of the JSON file orders are kept in; without it they live in memory.
+const ORDER_BOOK_VAR: &str = "CCH_ORDER_BOOK";
+/// How many submissions the book holds before refusing new ones.
+const CAPACITY_VAR: &str = "CCH_ORDER_BOOK_CAPACITY";
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Where a submission came from, so every order can be traced back to the
+/// manifest that produced it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub package: String,
+    pub version: Option<String>,
+    pub format: String,
+    /// SHA-256 of the body exactly as it was sent.
+    pub sha256: String,
+}
+impl Provenance {
+    pub fn new(package: String, version: Option<String>, format: &str, body: &[u8]) -> Self {
+        let sha256 = digest::digest(&digest::SHA256, body)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Self {
+            package,
+            version,
+            format: format.to_owned(),
+            sha256,
+        }
+    }
+}
+
+/// The accepted orders of a manifest that has yet to get an ID.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewSubmission {
+    pub provenance: Provenance,
+    pub orders: Vec<Order>,
+    /// Whether the orders hold stock in the catalog.
+    pub reserved: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Open,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Seconds since the Unix epoch.
+    pub at: u64,
+    pub action: String,
+    pub orders: Vec<Order>,
+}
+impl AuditEvent {
+    fn now(action: &str, orders: &[Order]) -> Self {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Self {
+            at,
+            action: action.to_owned(),
+            orders: orders.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Submission {
+    pub id: u64,
+    pub status: Status,
+    pub provenance: Provenance,
+    pub orders: Vec<Order>,
+    #[serde(default)]
+    pub reserved: bool,
+    /// Every change, oldest first, starting with the creation.
+    pub history: Vec<AuditEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BookError {
+    NotFound { id: u64 },
+    Cancelled { id: u64 },
+    InvalidOrders { report: Value },
+    Shortage { shortages: Vec<Shortage> },
+    Full { capacity: usize },
+    Storage { message: String },
+}
+impl BookError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound { .. } => "order_not_found",
+            Self::Cancelled { .. } => "order_cancelled",
+            Self::InvalidOrders { .. } => "invalid_orders",
+            Self::Shortage { .. } => "insufficient_stock",
+            Self::Full { .. } => "order_book_full",
+            Self::Storage { .. } => "storage_error",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound { .. } => StatusCode::NOT_FOUND,
+            Self::Cancelled { .. } | Self::Shortage { .. } => StatusCode::CONFLICT,
+            Self::InvalidOrders { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Full { .. } => StatusCode::INSUFFICIENT_STORAGE,
+            Self::Storage { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut body = json!({ "error": self.code(), "message": self.to_string() });
+        match self {
+            Self::NotFound { id } | Self::Cancelled { id } => body["id"] = json!(id),
+            Self::InvalidOrders { report } => body["report"] = report.clone(),
+            Self::Shortage { shortages } => {
+                body["shortages"] = catalog::shortages_to_json(shortages)
+            }
+            Self::Full { .. } | Self::Storage { .. } => {}
+        }
+        body
+    }
+}
+impl std::fmt::Display for BookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound { id } => write!(f, "no order with ID {}", id),
+            Self::Cancelled { id } => write!(f, "order {} has been cancelled", id),
+            Self::InvalidOrders { .. } => write!(f, "some orders are invalid"),
+            Self::Shortage { .. } => write!(f, "the catalog cannot cover the orders"),
+            Self::Full { capacity } => {
+                write!(f, "the order book is full at {} orders", capacity)
+            }
+            Self::Storage { message } => write!(f, "the order book is unavailable: {}", message),
+        }
+    }
+}
+impl IntoResponse for BookError {
+    fn into_response(self) -> Response {
+        (self.status_code(), Json(self.to_json())).into_response()
+    }
+}
+
+/// Somewhere submissions are kept.
+pub trait OrderStore: Send + Sync {
+    /// Stores `submission` under the next free ID and returns it.
+    fn create(&self, submission: NewSubmission) -> Result<Submission, BookError>;
+    fn get(&self, id: u64) -> Result<Option<Submission>, BookError>;
+    fn list(&self) -> Result<Vec<Submission>, BookError>;
+    /// Replaces the submission with the same ID.
+    fn update(&self, submission: Submission) -> Result<(), BookError>;
+}
+
+/// Builds the record of `new` under the next free ID of `submissions`.
+fn record(
+    submissions: &BTreeMap<u64, Submission>,
+    capacity: usize,
+    new: NewSubmission,
+) -> Result<Submission, BookError> {
+    if submissions.len() >= capacity {
+        return Err(BookError::Full { capacity });
+    }
+    Ok(Submission {
+        id: submissions.last_key_value().map_or(1, |(id, _)| id + 1),
+        status: Status::Open,
+        history: vec![AuditEvent::now("created", &new.orders)],
+        provenance: new.provenance,
+        orders: new.orders,
+        reserved: new.reserved,
+    })
+}
+
+fn replace(
+    submissions: &mut BTreeMap<u64, Submission>,
+    submission: Submission,
+) -> Result<(), BookError> {
+    match submissions.get_mut(&submission.id) {
+        Some(stored) => {
+            *stored = submission;
+            Ok(())
+        }
+        None => Err(BookError::NotFound { id: submission.id }),
+    }
+}
+
+/// Keeps up to `capacity` submissions, cancelled ones included.
+#[derive(Debug)]
+pub struct MemoryStore {
+    submissions: Mutex<BTreeMap<u64, Submission>>,
+    capacity: usize,
+}
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+impl MemoryStore {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with(vec![], capacity)
+    }
+
+    fn with(submissions: Vec<Submission>, capacity: usize) -> Self {
+        Self {
+            submissions: Mutex::new(
+                submissions
+                    .into_iter()
+                    .map(|submission| (submission.id, submission))
+                    .collect(),
+            ),
+            capacity,
+        }
+    }
+}
+impl OrderStore for MemoryStore {
+    fn create(&self, new: NewSubmission) -> Result<Submission, BookError> {
+        let mut submissions = self.submissions.lock().unwrap();
+        let submission = record(&submissions, self.capacity, new)?;
+        submissions.insert(submission.id, submission.clone());
+        Ok(submission)
+    }
+
+    fn get(&self, id: u64) -> Result<Option<Submission>, BookError> {
+        Ok(self.submissions.lock().unwrap().get(&id).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<Submission>, BookError> {
+        Ok(self.submissions.lock().unwrap().values().cloned().collect())
+    }
+
+    fn update(&self, submission: Submission) -> Result<(), BookError> {
+        replace(&mut self.submissions.lock().unwrap(), submission)
+    }
+}
+
+/// Keeps submissions in memory and rewrites a JSON file on every change,
+/// through a temporary file so a crash never leaves half of one behind.
+/// A change reaches memory only once its file is in place.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    memory: MemoryStore,
+}
+impl FileStore {
+    pub fn open(path: PathBuf, capacity: usize) -> Result<Self, BookError> {
+        let submissions = match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data).map_err(|e| BookError::Storage {
+                message: format!("{}: {}", path.display(), e),
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => {
+                return Err(BookError::Storage {
+                    message: format!("{}: {}", path.display(), e),
+                })
+            }
+        };
+        Ok(Self {
+            path,
+            memory: MemoryStore::with(submissions, capacity),
+        })
+    }
+
+    fn persist(&self, submissions: &BTreeMap<u64, Submission>) -> Result<(), BookError> {
+        let storage_error = |e: std::io::Error| BookError::Storage {
+            message: format!("{}: {}", self.path.display(), e),
+        };
+        let submissions: Vec<_> = submissions.values().collect();
+        let data = serde_json::to_string_pretty(&submissions).map_err(|e| BookError::Storage {
+            message: e.to_string(),
+        })?;
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, data).map_err(storage_error)?;
+        fs::rename(&temporary, &self.path).map_err(storage_error)
+    }
+}
+impl OrderStore for FileStore {
+    fn create(&self, new: NewSubmission) -> Result<Submission, BookError> {
+        // The lock is held across the write so files land in order.
+        let mut submissions = self.memory.submissions.lock().unwrap();
+        let submission = record(&submissions, self.memory.capacity, new)?;
+        let mut changed = submissions.clone();
+        changed.insert(submission.id, submission.clone());
+        self.persist(&changed)?;
+        *submissions = changed;
+        Ok(submission)
+    }
+
+    fn get(&self, id: u64) -> Result<Option<Submission>, BookError> {
+        self.memory.get(id)
+    }
+
+    fn list(&self) -> Result<Vec<Submission>, BookError> {
+        self.memory.list()
+    }
+
+    fn update(&self, submission: Submission) -> Result<(), BookError> {
+        let mut submissions = self.memory.submissions.lock().unwrap();
+        let mut changed = submissions.clone();
+        replace(&mut changed, submission)?;
+        self.persist(&changed)?;
+        *submissions = changed;
+        Ok(())
+    }
+}
+
+/// The submissions of `/5/manifest`, whatever store they are kept in.
+pub struct OrderBook {
+    store: Box<dyn OrderStore>,
+    /// Held while amending or cancelling, so stock is given back only once.
+    changes: Mutex<()>,
+}
+impl OrderBook {
+    pub fn new(store: Box<dyn OrderStore>) -> Self {
+        Self {
+            store,
+            changes: Mutex::new(()),
+        }
+    }
+
+    /// Uses a file store at `CCH_ORDER_BOOK` if set, memory otherwise, both
+    /// bounded by `CCH_ORDER_BOOK_CAPACITY`. A file that cannot be read is
+    /// reported and replaced by memory rather than overwritten.
+    pub fn shared_from_env() -> Arc<Self> {
+        let capacity = env::var(CAPACITY_VAR)
+            .ok()
+            .and_then(|capacity| capacity.parse().ok())
+            .unwrap_or(DEFAULT_CAPACITY);
+        let store: Box<dyn OrderStore> = match env::var_os(ORDER_BOOK_VAR) {
+            Some(path) => match FileStore::open(PathBuf::from(path), capacity) {
+                Ok(store) => Box::new(store),
+                Err(e) => {
+                    tracing::warn!(error = %e, "keeping orders in memory only");
+                    Box::new(MemoryStore::with_capacity(capacity))
+                }
+            },
+            None => Box::new(MemoryStore::with_capacity(capacity)),
+        };
+        Arc::new(Self::new(store))
+    }
+
+    pub fn submit(&self, new: NewSubmission) -> Result<Submission, BookError> {
+        self.store.create(new)
+    }
+
+    fn open_submission(&self, id: u64) -> Result<Submission, BookError> {
+        let submission = self.store.get(id)?.ok_or(BookError::NotFound { id })?;
+        match submission.status {
+            Status::Open => Ok(submission),
+            Status::Cancelled => Err(BookError::Cancelled { id }),
+        }
+    }
+
+    /// Replaces the orders of an open submission; all of them must be valid
+    /// and, unless the catalog is permissive, in stock.
+    fn amend(&self, id: u64, values: &[Value], catalog: &Catalog) -> Result<Submission, BookError> {
+        let reviewed: Vec<_> = values.iter().map(Order::try_from).collect();
+        if reviewed.is_empty() || reviewed.iter().any(Result::is_err) {
+            return Err(BookError::InvalidOrders {
+                report: order::report(&reviewed),
+            });
+        }
+        let orders: Vec<Order> = reviewed.into_iter().filter_map(Result::ok).collect();
+        let _guard = self.changes.lock().unwrap();
+        let mut submission = self.open_submission(id)?;
+        let was_reserved = submission.reserved;
+        let release = match was_reserved {
+            true => submission.orders.clone(),
+            false => vec![],
+        };
+        submission.reserved = catalog
+            .exchange(&release, &orders)
+            .map_err(|shortages| BookError::Shortage { shortages })?;
+        submission.orders = orders;
+        submission
+            .history
+            .push(AuditEvent::now("amended", &submission.orders));
+        if let Err(e) = self.store.update(submission.clone()) {
+            // Put the stock back the way the stored orders have it. Someone
+            // may have taken what was released meanwhile, which cannot be
+            // undone here.
+            if submission.reserved && catalog.exchange(&submission.orders, &release).is_err() {
+                tracing::error!(id, "stock no longer matches order after a failed amendment");
+            }
+            return Err(e);
+        }
+        Ok(submission)
+    }
+
+    /// Cancels an open submission, then gives back any stock it held.
+    fn cancel(&self, id: u64, catalog: &Catalog) -> Result<Submission, BookError> {
+        let _guard = self.changes.lock().unwrap();
+        let mut submission = self.open_submission(id)?;
+        let held = std::mem::take(&mut submission.reserved).then(|| submission.orders.clone());
+        submission.status = Status::Cancelled;
+        submission.history.push(AuditEvent::now("cancelled", &[]));
+        self.store.update(submission.clone())?;
+        if let Some(orders) = held {
+            // Giving stock back cannot run short.
+            let _ = catalog.exchange(&orders, &[]);
+        }
+        Ok(submission)
+    }
+
+    /// Submissions with the given status that order `item` (ignoring case).
+    fn query(
+        &self,
+        item: Option<&str>,
+        status: Option<Status>,
+    ) -> Result<Vec<Submission>, BookError> {
+        Ok(self
+            .store
+            .list()?
+            .into_iter()
+            .filter(|submission| status.is_none_or(|status| submission.status == status))
+            .filter(|submission| {
+                item.is_none_or(|item| {
+                    submission
+                        .orders
+                        .iter()
+                        .any(|order| order.item.eq_ignore_ascii_case(item))
+                })
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BookParams {
+    item: Option<String>,
+    status: Option<Status>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Amendment {
+    orders: Vec<Value>,
+}
+
+type SharedBook = Extension<Arc<OrderBook>>;
+type SharedCatalog = Extension<Arc<Catalog>>;
+
+/// Lists submissions, optionally only those ordering `item` or in `status`.
+pub async fn list_orders(
+    book_params: Query<BookParams>,
+    Extension(order_book): SharedBook,
+) -> Result<impl IntoResponse, BookError> {
+    let submissions = order_book.query(book_params.item.as_deref(), book_params.status)?;
+    Ok(Json(json!({ "orders": submissions })))
+}
+
+pub async fn show_order(
+    Path(id): Path<u64>,
+    Extension(order_book): SharedBook,
+) -> Result<impl IntoResponse, BookError> {
+    let submission = order_book
+        .store
+        .get(id)?
+        .ok_or(BookError::NotFound { id })?;
+    Ok(Json(submission))
+}
+
+/// Replaces the orders of a submission with `{"orders": [...]}`.
+pub async fn amend_order(
+    Path(id): Path<u64>,
+    Extension(order_book): SharedBook,
+    Extension(catalog): SharedCatalog,
+    Json(amendment): Json<Amendment>,
+) -> Result<impl IntoResponse, BookError> {
+    Ok(Json(order_book.amend(id, &amendment.orders, &catalog)?))
+}
+
+/// Cancels a submission; it stays in the book for the record.
+pub async fn cancel_order(
+    Path(id): Path<u64>,
+    Extension(order_book): SharedBook,
+    Extension(catalog): SharedCatalog,
+) -> Result<impl IntoResponse, BookError> {
+    Ok(Json(order_book.cancel(id, &catalog)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_submission(items: &[(&str, u32)]) -> NewSubmission {
+        NewSubmission {
+            provenance: Provenance::new("sleigh".to_owned(), None, "toml", b"[package]"),
+            orders: items
+                .iter()
+                .map(|(item, quantity)| Order {
+                    item: item.to_string(),
+                    quantity: *quantity,
+                })
+                .collect(),
+            reserved: false,
+        }
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let catalog = Catalog::default();
+        let order_book = OrderBook::new(Box::<MemoryStore>::default());
+        let first = order_book
+            .submit(new_submission(&[("Toy car", 2)]))
+            .unwrap();
+        let second = order_book
+            .submit(new_submission(&[("Lego brick", 23)]))
+            .unwrap();
+        assert_eq!((1, 2), (first.id, second.id));
+        assert_eq!(64, first.provenance.sha256.len());
+
+        let amended = order_book
+            .amend(
+                1,
+                &[json!({ "item": "Toy train", "quantity": 1 })],
+                &catalog,
+            )
+            .unwrap();
+        assert_eq!("Toy train", amended.orders[0].item);
+        assert_eq!(2, amended.history.len());
+        let err = order_book
+            .amend(1, &[json!({ "item": "Coal", "quantity": -1 })], &catalog)
+            .unwrap_err();
+        assert_eq!("invalid_orders", err.code());
+
+        assert_eq!(1, order_book.query(Some("toy TRAIN"), None).unwrap().len());
+        order_book.cancel(1, &catalog).unwrap();
+        assert_eq!(
+            "order_cancelled",
+            order_book.cancel(1, &catalog).unwrap_err().code()
+        );
+        assert_eq!(
+            vec![2],
+            order_book
+                .query(None, Some(Status::Open))
+                .unwrap()
+                .iter()
+                .map(|submission| submission.id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "order_not_found",
+            order_book.cancel(9, &catalog).unwrap_err().code()
+        );
+    }
+
+    #[test]
+    fn test_file_store() {
+        let path = env::temp_dir().join(format!("cch-orders-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let store = FileStore::open(path.clone(), DEFAULT_CAPACITY).unwrap();
+        store.create(new_submission(&[("Toy car", 2)])).unwrap();
+        let order_book = OrderBook::new(Box::new(store));
+        let catalog = Catalog::default();
+        order_book.cancel(1, &catalog).unwrap();
+
+        let reopened = FileStore::open(path.clone(), DEFAULT_CAPACITY).unwrap();
+        let submission = reopened.get(1).unwrap().unwrap();
+        assert_eq!(Status::Cancelled, submission.status);
+        assert_eq!(
+            vec!["created", "cancelled"],
+            submission
+                .history
+                .iter()
+                .map(|event| event.action.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2, reopened.create(new_submission(&[])).unwrap().id);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stock() {
+        let catalog = Catalog::enforced();
+        catalog.set("Toy car", 3);
+        let order_book = OrderBook::new(Box::<MemoryStore>::default());
+        let mut new = new_submission(&[("Toy car", 2)]);
+        new.reserved = catalog.exchange(&[], &new.orders).unwrap();
+        order_book.submit(new).unwrap();
+
+        let err = order_book
+            .amend(1, &[json!({ "item": "Toy car", "quantity": 4 })], &catalog)
+            .unwrap_err();
+        assert_eq!("insufficient_stock", err.code());
+        order_book
+            .amend(1, &[json!({ "item": "Toy car", "quantity": 3 })], &catalog)
+            .unwrap();
+        assert!(catalog
+            .exchange(&[], &new_submission(&[("Toy car", 1)]).orders)
+            .is_err());
+
+        let cancelled = order_book.cancel(1, &catalog).unwrap();
+        assert!(!cancelled.reserved);
+        assert_eq!(
+            Ok(true),
+            catalog.exchange(&[], &new_submission(&[("Toy car", 3)]).orders)
+        );
+    }
+
+    #[test]
+    fn test_failed_writes() {
+        let dir = env::temp_dir().join(format!("cch-orders-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let catalog = Catalog::enforced();
+        catalog.set("Toy car", 3);
+        let order_book = OrderBook::new(Box::new(
+            FileStore::open(dir.join("orders.json"), 1).unwrap(),
+        ));
+        let mut new = new_submission(&[("Toy car", 2)]);
+        new.reserved = catalog.exchange(&[], &new.orders).unwrap();
+        order_book.submit(new).unwrap();
+        assert_eq!(
+            "order_book_full",
+            order_book.submit(new_submission(&[])).unwrap_err().code()
+        );
+
+        // Neither memory nor stock changes when the file cannot be written.
+        fs::remove_dir_all(&dir).unwrap();
+        let err = order_book.cancel(1, &catalog).unwrap_err();
+        assert_eq!("storage_error", err.code());
+        let err = order_book
+            .amend(1, &[json!({ "item": "Toy car", "quantity": 3 })], &catalog)
+            .unwrap_err();
+        assert_eq!("storage_error", err.code());
+        let submission = order_book.store.get(1).unwrap().unwrap();
+        assert_eq!(
+            (Status::Open, 2),
+            (submission.status, submission.orders[0].quantity)
+        );
+        assert!(catalog
+            .exchange(&[], &new_submission(&[("Toy car", 2)]).orders)
+            .is_err());
+        assert_eq!(
+            Ok(true),
+            catalog.exchange(&[], &new_submission(&[("Toy car", 1)]).orders)
+        );
+
+        let store = FileStore::open(dir.join("orders.json"), 1).unwrap();
+        assert!(store.create(new_submission(&[])).is_err());
+        assert_eq!(Ok(vec![]), store.list());
+    }
+}
@@ -0,0 +1,432 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::Path as FilePath,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::order::Order;
+
+/// TOML file of initial stock, one `"item" = quantity` per line.
+const CATALOG_VAR: &str = "CCH_CATALOG";
+/// `enforce` checks every order against the catalog and rejects unknown
+/// items; `permissive` leaves orders unchecked. Defaults to `enforce` when
+/// `CCH_CATALOG` is set and `permissive` otherwise.
+const MODE_VAR: &str = "CCH_CATALOG_MODE";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Stock {
+    /// The name as the catalog spells it; lookups ignore case.
+    item: String,
+    available: u64,
+    reserved: u64,
+}
+
+/// An item a manifest asked for more of than the catalog could give.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shortage {
+    pub item: String,
+    pub requested: u64,
+    /// `None` when the catalog has no such item.
+    pub available: Option<u64>,
+}
+impl Shortage {
+    fn code(&self) -> &'static str {
+        match self.available {
+            Some(_) => "insufficient_stock",
+            None => "unknown_item",
+        }
+    }
+}
+
+pub fn shortages_to_json(shortages: &[Shortage]) -> Value {
+    shortages
+        .iter()
+        .map(|shortage| {
+            json!({
+                "item": shortage.item,
+                "reason": shortage.code(),
+                "requested": shortage.requested,
+                "available": shortage.available,
+            })
+        })
+        .collect()
+}
+
+/// Turns a list of shortages into a `409`, as JSON or one line per item.
+pub fn shortage_report(shortages: &[Shortage], json: bool) -> (StatusCode, String) {
+    let body = match json {
+        true => json!({
+            "error": "insufficient_stock",
+            "shortages": shortages_to_json(shortages),
+        })
+        .to_string(),
+        false => shortages
+            .iter()
+            .map(|shortage| match shortage.available {
+                Some(available) => format!(
+                    "{}: {} requested, {} available",
+                    shortage.item, shortage.requested, available
+                ),
+                None => format!("{}: not in the catalog", shortage.item),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    (StatusCode::CONFLICT, body)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CatalogError {
+    NotFound { item: String },
+    StockOverflow { item: String },
+}
+impl CatalogError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound { .. } => "item_not_found",
+            Self::StockOverflow { .. } => "stock_overflow",
+        }
+    }
+}
+impl std::fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound { item } => write!(f, "{:?} is not in the catalog", item),
+            Self::StockOverflow { item } => write!(f, "stock of {:?} would overflow", item),
+        }
+    }
+}
+impl IntoResponse for CatalogError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            Self::NotFound { .. } => StatusCode::NOT_FOUND,
+            Self::StockOverflow { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        };
+        let body = json!({ "error": self.code(), "message": self.to_string() });
+        (status_code, Json(body)).into_response()
+    }
+}
+
+/// The stock after giving back `release` and taking `reserve`, or what
+/// `reserve` is short of.
+fn plan(
+    items: &BTreeMap<String, Stock>,
+    release: &[Order],
+    reserve: &[Order],
+) -> Result<BTreeMap<String, Stock>, Vec<Shortage>> {
+    // Released items that have since left the catalog are dropped.
+    let mut after = items.clone();
+    for order in release {
+        if let Some(stock) = after.get_mut(&order.item.to_lowercase()) {
+            let quantity = u64::from(order.quantity).min(stock.reserved);
+            stock.reserved -= quantity;
+            stock.available = stock.available.saturating_add(quantity);
+        }
+    }
+
+    let mut requested = BTreeMap::<String, (&str, u64)>::new();
+    for order in reserve {
+        let (_, quantity) = requested
+            .entry(order.item.to_lowercase())
+            .or_insert((&order.item, 0));
+        *quantity += u64::from(order.quantity);
+    }
+    let mut shortages = vec![];
+    for (key, (item, quantity)) in requested {
+        match after.get_mut(&key) {
+            Some(stock) if stock.available >= quantity => {
+                stock.available -= quantity;
+                stock.reserved += quantity;
+            }
+            stock => shortages.push(Shortage {
+                item: item.to_owned(),
+                requested: quantity,
+                available: stock.map(|stock| stock.available),
+            }),
+        }
+    }
+    match shortages.is_empty() {
+        true => Ok(after),
+        false => Err(shortages),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inventory {
+    /// Keyed by lowercased item name.
+    items: BTreeMap<String, Stock>,
+}
+
+/// Items and how many of each are left. Every change happens under one lock,
+/// so a manifest's orders are reserved all together or not at all.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    inventory: Mutex<Inventory>,
+    /// Unless set, orders are taken as they come and no stock moves.
+    enforced: bool,
+}
+impl Catalog {
+    pub fn enforced() -> Self {
+        Self {
+            enforced: true,
+            ..Self::default()
+        }
+    }
+
+    /// Loads the initial stock from `CCH_CATALOG` if set, in the mode of
+    /// `CCH_CATALOG_MODE`. Without either the catalog is permissive, so
+    /// manifests are accepted as before. A file that cannot be read leaves
+    /// the catalog empty, and an unknown mode enforces it.
+    pub fn shared_from_env() -> Arc<Self> {
+        let path = env::var_os(CATALOG_VAR);
+        let catalog = match env::var(MODE_VAR).as_deref() {
+            Ok("permissive") => Self::default(),
+            Ok("enforce") => Self::enforced(),
+            Err(_) if path.is_none() => Self::default(),
+            Err(_) => Self::enforced(),
+            Ok(mode) => {
+                tracing::warn!(
+                    "ignoring invalid {} {:?}, enforcing the catalog",
+                    MODE_VAR,
+                    mode
+                );
+                Self::enforced()
+            }
+        };
+        if let Some(path) = path {
+            if let Err(e) = catalog.load(FilePath::new(&path)) {
+                tracing::warn!(error = %e, "starting with an empty catalog");
+            }
+        }
+        Arc::new(catalog)
+    }
+
+    fn load(&self, path: &FilePath) -> Result<(), String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let stock = toml::from_str::<BTreeMap<String, u64>>(&data)
+            .map_err(|e| format!("{}: {}", path.display(), e.message()))?;
+        for (item, available) in stock {
+            self.set(&item, available);
+        }
+        Ok(())
+    }
+
+    /// Sets how many of `item` are available, adding it if needed.
+    pub fn set(&self, item: &str, available: u64) {
+        let mut inventory = self.inventory.lock().unwrap();
+        inventory
+            .items
+            .entry(item.to_lowercase())
+            .and_modify(|stock| stock.available = available)
+            .or_insert_with(|| Stock {
+                item: item.to_owned(),
+                available,
+                reserved: 0,
+            });
+    }
+
+    pub fn restock(&self, item: &str, quantity: u64) -> Result<u64, CatalogError> {
+        let mut inventory = self.inventory.lock().unwrap();
+        let stock = inventory
+            .items
+            .get_mut(&item.to_lowercase())
+            .ok_or_else(|| CatalogError::NotFound {
+                item: item.to_owned(),
+            })?;
+        stock.available =
+            stock
+                .available
+                .checked_add(quantity)
+                .ok_or_else(|| CatalogError::StockOverflow {
+                    item: item.to_owned(),
+                })?;
+        Ok(stock.available)
+    }
+
+    pub fn remove(&self, item: &str) -> Result<(), CatalogError> {
+        let mut inventory = self.inventory.lock().unwrap();
+        match inventory.items.remove(&item.to_lowercase()) {
+            Some(_) => Ok(()),
+            None => Err(CatalogError::NotFound {
+                item: item.to_owned(),
+            }),
+        }
+    }
+
+    /// Gives back the stock of `release` and takes that of `reserve`, in one
+    /// step: if anything in `reserve` is unknown or short, nothing changes.
+    /// Returns whether any stock moved, which it never does when permissive.
+    pub fn exchange(&self, release: &[Order], reserve: &[Order]) -> Result<bool, Vec<Shortage>> {
+        if !self.enforced {
+            return Ok(false);
+        }
+        let mut inventory = self.inventory.lock().unwrap();
+        inventory.items = plan(&inventory.items, release, reserve)?;
+        Ok(true)
+    }
+
+    fn to_json(&self) -> Value {
+        let inventory = self.inventory.lock().unwrap();
+        json!({
+            "mode": if self.enforced { "enforce" } else { "permissive" },
+            "items": inventory
+                .items
+                .values()
+                .map(|stock| json!({
+                    "item": stock.item,
+                    "available": stock.available,
+                    "reserved": stock.reserved,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StockLevel {
+    available: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Restock {
+    quantity: u64,
+}
+
+type SharedCatalog = Extension<Arc<Catalog>>;
+
+pub async fn show_catalog(Extension(catalog): SharedCatalog) -> impl IntoResponse {
+    Json(catalog.to_json())
+}
+
+/// Sets the available stock of an item with `{"available": n}`.
+pub async fn set_stock(
+    Path(item): Path<String>,
+    Extension(catalog): SharedCatalog,
+    Json(stock_level): Json<StockLevel>,
+) -> impl IntoResponse {
+    catalog.set(&item, stock_level.available);
+    Json(json!({ "item": item, "available": stock_level.available }))
+}
+
+/// Adds `{"quantity": n}` to the available stock of an item.
+pub async fn restock_item(
+    Path(item): Path<String>,
+    Extension(catalog): SharedCatalog,
+    Json(restock): Json<Restock>,
+) -> Result<impl IntoResponse, CatalogError> {
+    let available = catalog.restock(&item, restock.quantity)?;
+    Ok(Json(json!({ "item": item, "available": available })))
+}
+
+pub async fn remove_item(
+    Path(item): Path<String>,
+    Extension(catalog): SharedCatalog,
+) -> Result<impl IntoResponse, CatalogError> {
+    catalog.remove(&item)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orders(items: &[(&str, u32)]) -> Vec<Order> {
+        items
+            .iter()
+            .map(|(item, quantity)| Order {
+                item: item.to_string(),
+                quantity: *quantity,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_modes() {
+        let unknown = orders(&[("Coal", 9)]);
+        let shortages = Catalog::enforced().exchange(&[], &unknown).unwrap_err();
+        assert_eq!(None, shortages[0].available);
+
+        let catalog = Catalog::default();
+        assert_eq!(Ok(false), catalog.exchange(&[], &unknown));
+        assert_eq!("permissive", catalog.to_json()["mode"]);
+    }
+
+    #[test]
+    fn test_reserve_all_or_nothing() {
+        let catalog = Catalog::enforced();
+        catalog.set("Toy car", 5);
+        catalog.set("Lego brick", 30);
+
+        let shortages = catalog
+            .exchange(
+                &[],
+                &orders(&[
+                    ("Toy car", 4),
+                    ("toy car", 2),
+                    ("Lego brick", 1),
+                    ("Coal", 1),
+                ]),
+            )
+            .unwrap_err();
+        assert_eq!(
+            vec![
+                Shortage {
+                    item: "Coal".to_owned(),
+                    requested: 1,
+                    available: None,
+                },
+                Shortage {
+                    item: "Toy car".to_owned(),
+                    requested: 6,
+                    available: Some(5),
+                },
+            ],
+            shortages
+        );
+        assert_eq!(
+            "Coal: not in the catalog\nToy car: 6 requested, 5 available",
+            shortage_report(&shortages, false).1
+        );
+        // The Lego bricks that were available were not taken either.
+        assert_eq!(30, catalog.to_json()["items"][0]["available"]);
+
+        let reserved = orders(&[("Toy car", 4), ("Lego brick", 23)]);
+        assert_eq!(Ok(true), catalog.exchange(&[], &reserved));
+        assert_eq!(1, catalog.to_json()["items"][1]["available"]);
+        assert_eq!(4, catalog.to_json()["items"][1]["reserved"]);
+
+        // Swapping orders gives back the old stock before taking the new.
+        assert_eq!(
+            Ok(true),
+            catalog.exchange(&reserved, &orders(&[("Toy car", 5)]))
+        );
+        assert_eq!(30, catalog.to_json()["items"][0]["available"]);
+        assert_eq!(0, catalog.to_json()["items"][1]["available"]);
+    }
+
+    #[test]
+    fn test_admin() {
+        let catalog = Catalog::enforced();
+        catalog.set("Toy car", 1);
+        assert_eq!(Ok(3), catalog.restock("TOY CAR", 2));
+        assert_eq!(
+            "stock_overflow",
+            catalog.restock("Toy car", u64::MAX).unwrap_err().code()
+        );
+        assert_eq!(Ok(()), catalog.remove("toy car"));
+        assert_eq!(
+            "item_not_found",
+            catalog.remove("Toy car").unwrap_err().code()
+        );
+    }
+}
@@ -0,0 +1,491 @@
+use std::fmt;
+
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::IntoResponse,
+};
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::json;
+
+use super::{diagnostic::Diagnostic, wants_json, Format, Validation};
+
+/// The key under which `toml` smuggles a datetime through serde.
+const TOML_DATETIME_FIELD: &str = "$__toml_private_datetime";
+/// Response header listing the paths of values a lossy conversion changed.
+const LOSSY_HEADER: &str = "x-lossy-paths";
+
+/// A document read from any of the three formats, with tables kept in the
+/// order their keys were written.
+#[derive(Debug, Clone, PartialEq)]
+enum Doc {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    /// An integer above `i64::MAX`, which TOML cannot hold.
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Datetime(String),
+    Array(Vec<Doc>),
+    Table(Vec<(String, Doc)>),
+}
+
+impl<'de> Deserialize<'de> for Doc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(DocVisitor)
+    }
+}
+
+struct DocVisitor;
+impl<'de> Visitor<'de> for DocVisitor {
+    type Value = Doc;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a TOML, JSON or YAML value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Doc, E> {
+        Ok(Doc::Bool(value))
+    }
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Doc, E> {
+        Ok(Doc::Integer(value))
+    }
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Doc, E> {
+        Ok(i64::try_from(value).map_or(Doc::UInt(value), Doc::Integer))
+    }
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Doc, E> {
+        Ok(Doc::Float(value))
+    }
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Doc, E> {
+        Ok(Doc::String(value.to_owned()))
+    }
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Doc, E> {
+        Ok(Doc::String(value))
+    }
+    fn visit_unit<E: de::Error>(self) -> Result<Doc, E> {
+        Ok(Doc::Null)
+    }
+    fn visit_none<E: de::Error>(self) -> Result<Doc, E> {
+        Ok(Doc::Null)
+    }
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Doc, D::Error> {
+        Doc::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Doc, A::Error> {
+        let mut items = vec![];
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Doc::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Doc, A::Error> {
+        let mut entries = vec![];
+        while let Some(key) = map.next_key::<Key>()? {
+            let value: Doc = map.next_value()?;
+            if key.0 == TOML_DATETIME_FIELD {
+                if let Doc::String(datetime) = value {
+                    return Ok(Doc::Datetime(datetime));
+                }
+            }
+            entries.push((key.0, value));
+        }
+        Ok(Doc::Table(entries))
+    }
+}
+
+/// A table key. YAML allows scalars of any type as keys; they are kept as
+/// the text they were written as.
+struct Key(String);
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Doc::deserialize(deserializer)? {
+            Doc::String(key) => Ok(Key(key)),
+            Doc::Bool(key) => Ok(Key(key.to_string())),
+            Doc::Integer(key) => Ok(Key(key.to_string())),
+            Doc::UInt(key) => Ok(Key(key.to_string())),
+            Doc::Float(key) => Ok(Key(key.to_string())),
+            _ => Err(de::Error::custom("table keys must be scalars")),
+        }
+    }
+}
+
+impl Serialize for Doc {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Null => serializer.serialize_unit(),
+            Self::Bool(value) => serializer.serialize_bool(*value),
+            Self::Integer(value) => serializer.serialize_i64(*value),
+            Self::UInt(value) => serializer.serialize_u64(*value),
+            Self::Float(value) => serializer.serialize_f64(*value),
+            Self::String(value) => serializer.serialize_str(value),
+            Self::Datetime(value) => value
+                .parse::<toml::value::Datetime>()
+                .map_err(serde::ser::Error::custom)?
+                .serialize(serializer),
+            Self::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Self::Table(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// A value the target format has no way to write.
+#[derive(Debug, Clone, PartialEq)]
+struct Issue {
+    path: String,
+    message: &'static str,
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_owned(),
+        path => format!("{}.{}", path, key),
+    }
+}
+
+/// Rewrites `doc` into something `target` can hold, noting every value that
+/// had to change. `None` means the value is dropped altogether.
+fn adapt(doc: Doc, target: Format, path: &str, issues: &mut Vec<Issue>) -> Option<Doc> {
+    let mut issue = |message| {
+        issues.push(Issue {
+            path: path.to_owned(),
+            message,
+        })
+    };
+    match (doc, target) {
+        (Doc::Null, Format::Toml) => {
+            issue("null has no TOML equivalent and was left out");
+            None
+        }
+        (Doc::UInt(value), Format::Toml) => {
+            issue("integers above 2^63-1 do not fit TOML and were written as strings");
+            Some(Doc::String(value.to_string()))
+        }
+        (Doc::Float(value), Format::Json) if !value.is_finite() => {
+            issue("NaN and infinities have no JSON equivalent and were written as null");
+            Some(Doc::Null)
+        }
+        (Doc::Datetime(value), Format::Json | Format::Yaml) => {
+            issue("TOML datetimes were written as strings");
+            Some(Doc::String(value))
+        }
+        (Doc::Array(items), target) => Some(Doc::Array(
+            items
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, item)| {
+                    adapt(item, target, &child_path(path, &index.to_string()), issues)
+                })
+                .collect(),
+        )),
+        (Doc::Table(entries), target) => Some(Doc::Table(
+            entries
+                .into_iter()
+                .filter_map(|(key, value)| {
+                    let value = adapt(value, target, &child_path(path, &key), issues)?;
+                    Some((key, value))
+                })
+                .collect(),
+        )),
+        (doc, _) => Some(doc),
+    }
+}
+
+impl Format {
+    fn mime_type(self) -> &'static str {
+        match self {
+            Self::Toml => super::TOML_MIME_TYPE,
+            Self::Json => super::JSON_MIME_TYPE,
+            Self::Yaml => super::YAML_MIME_TYPE,
+        }
+    }
+
+    fn parse_doc(self, text: &str) -> Result<Doc, Diagnostic> {
+        match self {
+            Self::Toml => toml::from_str(text).map_err(|e| Diagnostic::from_toml(text, true, e)),
+            Self::Json => serde_json::from_str(text).map_err(|e| Diagnostic::from_json(text, e)),
+            Self::Yaml => {
+                serde_yaml::from_str(text).map_err(|e| Diagnostic::from_yaml(text, true, e))
+            }
+        }
+        .map_err(|diagnostic| diagnostic.with_format(self))
+    }
+
+    fn write_doc(self, doc: &Doc) -> Result<String, String> {
+        match self {
+            Self::Toml => toml::to_string(doc).map_err(|e| e.to_string()),
+            Self::Json => serde_json::to_string_pretty(doc)
+                .map(|json| json + "\n")
+                .map_err(|e| e.to_string()),
+            Self::Yaml => serde_yaml::to_string(doc).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// The first format the `Accept` header names; none, or only wildcards,
+/// means the format the body came in.
+fn accepted_format(headers: &HeaderMap, source: Format) -> Option<Format> {
+    let ranges: Vec<String> = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|range| range.trim().to_owned())
+        .filter(|range| !range.is_empty())
+        .collect();
+    if ranges.is_empty() {
+        return Some(source);
+    }
+    ranges.iter().find_map(|range| {
+        let essence = range.split(';').next().unwrap_or_default().trim();
+        match essence {
+            "*/*" | "application/*" => Some(source),
+            _ => Format::from_content_type(range),
+        }
+    })
+}
+
+fn convert(lossy: bool, headers: &HeaderMap, data: &[u8]) -> Validation {
+    let Some(source) = headers
+        .get(CONTENT_TYPE)
+        .and_then(|header_type| header_type.to_str().ok())
+        .and_then(Format::from_content_type)
+    else {
+        return Validation::status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    };
+    let Some(target) = accepted_format(headers, source) else {
+        return Validation::status(StatusCode::NOT_ACCEPTABLE);
+    };
+    let json = wants_json(headers);
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) => {
+            return Validation::invalid_manifest(Diagnostic::new("encoding", e.to_string()), json)
+        }
+    };
+    let doc = match source.parse_doc(text) {
+        Ok(doc) => doc,
+        Err(diagnostic) => return Validation::invalid_manifest(diagnostic, json),
+    };
+    if target == Format::Toml && !matches!(doc, Doc::Table(_)) {
+        let diagnostic = Diagnostic::new("data", "a TOML document must be a table");
+        return Validation::invalid_manifest(diagnostic.with_format(source), json);
+    }
+
+    let mut issues = vec![];
+    let doc = adapt(doc, target, "", &mut issues).unwrap_or(Doc::Null);
+    if !issues.is_empty() && !lossy {
+        let issues: Vec<_> = issues
+            .iter()
+            .map(|issue| json!({ "path": issue.path, "message": issue.message }))
+            .collect();
+        return Validation::json(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            json!({
+                "error": "unrepresentable",
+                "from": source.name(),
+                "to": target.name(),
+                "issues": issues,
+            }),
+        );
+    }
+    let body = match target.write_doc(&doc) {
+        Ok(body) => body,
+        Err(message) => {
+            let diagnostic = Diagnostic::new("data", message).with_format(target);
+            return Validation::invalid_manifest(diagnostic, json);
+        }
+    };
+
+    let mut header = HeaderMap::new();
+    header.insert(CONTENT_TYPE, HeaderValue::from_static(target.mime_type()));
+    if !issues.is_empty() {
+        let paths: Vec<_> = issues.iter().map(|issue| issue.path.as_str()).collect();
+        if let Ok(paths) = HeaderValue::from_str(&paths.join(", ")) {
+            header.insert(LOSSY_HEADER, paths);
+        }
+    }
+    Validation {
+        status_code: StatusCode::OK,
+        header,
+        body,
+        ..Validation::default()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ConvertParams {
+    lossy: Option<bool>,
+}
+
+/// Re-serialises a TOML, JSON or YAML body (per Content-Type) in the format
+/// asked for by `Accept`. Values the target cannot hold are refused with a
+/// `422` listing them, unless `lossy=true` allows them to be rewritten.
+pub async fn convert_manifest(
+    convert_params: Query<ConvertParams>,
+    headers: HeaderMap,
+    data: Bytes,
+) -> impl IntoResponse {
+    let validation = convert(convert_params.lossy.unwrap_or_default(), &headers, &data);
+    (validation.status_code, validation.header, validation.body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(content_type: &str, accept: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        headers.insert(ACCEPT, HeaderValue::from_str(accept).unwrap());
+        headers
+    }
+
+    const TOML: &str = "[package]
+name = \"sleigh\"
+version = \"0.1.0\"
+authors = [\"Santa\"]
+
+[[package.metadata.orders]]
+quantity = 5
+item = \"Toy train\"
+";
+
+    #[test]
+    fn test_toml_to_json_keeps_order() {
+        let converted = convert(
+            false,
+            &headers("application/toml", "application/json"),
+            TOML.as_bytes(),
+        );
+        assert_eq!(StatusCode::OK, converted.status_code);
+        assert_eq!("application/json", converted.header[CONTENT_TYPE]);
+        let name = converted.body.find("\"name\"").unwrap();
+        let version = converted.body.find("\"version\"").unwrap();
+        let quantity = converted.body.find("\"quantity\"").unwrap();
+        let item = converted.body.find("\"item\"").unwrap();
+        assert!(name < version && quantity < item);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let yaml = convert(
+            false,
+            &headers("application/toml", "application/yaml"),
+            TOML.as_bytes(),
+        );
+        assert!(yaml.body.starts_with("package:\n  name: sleigh\n"));
+        let toml = convert(
+            false,
+            &headers("application/yaml", "application/toml"),
+            yaml.body.as_bytes(),
+        );
+        assert_eq!(
+            toml::from_str::<toml::Table>(TOML).unwrap(),
+            toml::from_str::<toml::Table>(&toml.body).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unrepresentable() {
+        let toml = "[package]\nname = \"x\"\nreleased = 2024-12-05T00:00:00Z\n";
+        let converted = convert(
+            false,
+            &headers("application/toml", "application/json"),
+            toml.as_bytes(),
+        );
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, converted.status_code);
+        let report: serde_json::Value = serde_json::from_str(&converted.body).unwrap();
+        assert_eq!("package.released", report["issues"][0]["path"]);
+
+        let converted = convert(
+            true,
+            &headers("application/toml", "application/json"),
+            toml.as_bytes(),
+        );
+        assert_eq!(StatusCode::OK, converted.status_code);
+        assert!(converted
+            .body
+            .contains("\"released\": \"2024-12-05T00:00:00Z\""));
+        assert_eq!("package.released", converted.header[LOSSY_HEADER]);
+
+        // Datetimes survive a TOML round trip untouched.
+        let converted = convert(
+            false,
+            &headers("application/toml", "application/toml"),
+            toml.as_bytes(),
+        );
+        assert!(converted.body.contains("released = 2024-12-05T00:00:00Z"));
+
+        let yaml = "package:\n  name: x\n  description: ~\n  keywords: [a, null]\n";
+        let converted = convert(
+            false,
+            &headers("application/yaml", "application/toml"),
+            yaml.as_bytes(),
+        );
+        let report: serde_json::Value = serde_json::from_str(&converted.body).unwrap();
+        assert_eq!("package.description", report["issues"][0]["path"]);
+        assert_eq!("package.keywords.1", report["issues"][1]["path"]);
+        let converted = convert(
+            true,
+            &headers("application/yaml", "application/toml"),
+            yaml.as_bytes(),
+        );
+        assert_eq!(
+            "[package]\nname = \"x\"\nkeywords = [\"a\"]\n",
+            converted.body
+        );
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let converted = convert(
+            false,
+            &headers("application/toml", "text/html"),
+            TOML.as_bytes(),
+        );
+        assert_eq!(StatusCode::NOT_ACCEPTABLE, converted.status_code);
+        let converted = convert(
+            false,
+            &headers("text/plain", "application/json"),
+            TOML.as_bytes(),
+        );
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, converted.status_code);
+        let converted = convert(false, &headers("application/toml", "*/*"), TOML.as_bytes());
+        assert_eq!("application/toml", converted.header[CONTENT_TYPE]);
+        let converted = convert(
+            false,
+            &headers("application/json", "application/toml"),
+            b"[1, 2]",
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, converted.status_code);
+        let converted = convert(
+            false,
+            &headers("application/json", "application/toml"),
+            b"{\"a\": ",
+        );
+        assert!(converted.body.starts_with("Invalid manifest: "));
+    }
+}
@@ -0,0 +1,184 @@
+use serde_json::{json, Value};
+
+use super::Format;
+
+/// Why a body could not be read as a manifest, and where, as far as the
+/// parser that rejected it could tell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// `encoding`, `syntax` or `data`.
+    pub kind: &'static str,
+    pub format: Option<Format>,
+    pub message: String,
+    /// One-based line and column.
+    pub location: Option<(usize, usize)>,
+    pub snippet: Option<String>,
+}
+impl Diagnostic {
+    pub fn new(kind: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            format: None,
+            message: message.into(),
+            location: None,
+            snippet: None,
+        }
+    }
+
+    fn at(mut self, text: &str, line: usize, column: usize) -> Self {
+        self.location = Some((line, column));
+        self.snippet = snippet(text, line, column);
+        self
+    }
+
+    fn at_offset(self, text: &str, offset: usize) -> Self {
+        let offset = offset.min(text.len());
+        let before = &text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let line = before.matches('\n').count() + 1;
+        let column = before[line_start..].chars().count() + 1;
+        self.at(text, line, column)
+    }
+
+    pub fn from_toml(text: &str, syntax: bool, e: toml::de::Error) -> Self {
+        let diagnostic = Self::new(kind(syntax), e.message().trim_end());
+        match e.span() {
+            Some(span) => diagnostic.at_offset(text, span.start),
+            None => diagnostic,
+        }
+    }
+
+    pub fn from_json(text: &str, e: serde_json::Error) -> Self {
+        let kind = match e.classify() {
+            serde_json::error::Category::Data => "data",
+            _ => "syntax",
+        };
+        let message = e.to_string();
+        let suffix = format!(" at line {} column {}", e.line(), e.column());
+        let diagnostic = Self::new(kind, message.strip_suffix(&suffix).unwrap_or(&message));
+        match e.line() {
+            0 => diagnostic,
+            line => diagnostic.at(text, line, e.column().max(1)),
+        }
+    }
+
+    pub fn from_yaml(text: &str, syntax: bool, e: serde_yaml::Error) -> Self {
+        let message = e.to_string();
+        let Some(location) = e.location() else {
+            return Self::new(kind(syntax), message);
+        };
+        let suffix = format!(" at line {} column {}", location.line(), location.column());
+        let message = message.strip_suffix(&suffix).unwrap_or(&message);
+        Self::new(kind(syntax), message).at_offset(text, location.index())
+    }
+
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "error": "invalid_manifest",
+            "kind": self.kind,
+            "format": self.format.map(Format::name),
+            "message": self.message,
+            "line": self.location.map(|(line, _)| line),
+            "column": self.location.map(|(_, column)| column),
+            "snippet": self.snippet,
+        })
+    }
+
+    /// The plain-text form: the message, then the location and snippet in
+    /// the style of rustc.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("Invalid manifest: {}", self.message);
+        if let Some((line, column)) = self.location {
+            text.push_str(&format!("\n --> line {}, column {}", line, column));
+        }
+        if let (Some(snippet), Some((line, _))) = (&self.snippet, self.location) {
+            let gutter = " ".repeat(line.to_string().len());
+            let mut lines = snippet.lines();
+            text.push_str(&format!("\n{} |", gutter));
+            text.push_str(&format!(
+                "\n{} | {}",
+                line,
+                lines.next().unwrap_or_default()
+            ));
+            text.push_str(&format!(
+                "\n{} | {}",
+                gutter,
+                lines.next().unwrap_or_default()
+            ));
+        }
+        text
+    }
+}
+
+fn kind(syntax: bool) -> &'static str {
+    if syntax {
+        "syntax"
+    } else {
+        "data"
+    }
+}
+
+/// The offending line with a caret under `column` on the next one.
+fn snippet(text: &str, line: usize, column: usize) -> Option<String> {
+    let source = text.lines().nth(line.checked_sub(1)?)?;
+    let caret_at = source
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    Some(format!("{}\n{}^", source, caret_at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cargo_manifest::Manifest;
+
+    #[test]
+    fn test_toml_data_error() {
+        let text = "[package]\nname = false\n";
+        let e = toml::from_str::<Manifest<Value>>(text).unwrap_err();
+        let diagnostic = Diagnostic::from_toml(text, false, e);
+        assert_eq!("data", diagnostic.kind);
+        assert_eq!(Some((2, 8)), diagnostic.location);
+        assert_eq!(
+            Some("name = false\n       ^"),
+            diagnostic.snippet.as_deref()
+        );
+        assert_eq!(
+            "Invalid manifest: invalid type: boolean `false`, expected a string\n --> line 2, column 8\n  |\n2 | name = false\n  |        ^",
+            diagnostic.to_text()
+        );
+    }
+
+    #[test]
+    fn test_json_syntax_error() {
+        let text = "{\n  \"package\": {\n    \"name\": \"x\",\n  }\n}";
+        let e = serde_json::from_str::<Manifest<Value>>(text).unwrap_err();
+        let diagnostic = Diagnostic::from_json(text, e).with_format(Format::Json);
+        assert_eq!("syntax", diagnostic.kind);
+        assert_eq!(Some((4, 3)), diagnostic.location);
+        assert_eq!("trailing comma", diagnostic.message);
+        let report = diagnostic.to_json();
+        assert_eq!("json", report["format"]);
+        assert_eq!("  }\n  ^", report["snippet"]);
+    }
+
+    #[test]
+    fn test_yaml_data_error() {
+        let text = "package:\n  name: [a, b]\n";
+        let e = serde_yaml::from_str::<Manifest<Value>>(text).unwrap_err();
+        let diagnostic = Diagnostic::from_yaml(text, false, e);
+        assert_eq!(Some((2, 9)), diagnostic.location);
+        assert!(!diagnostic.message.contains("at line"));
+        assert_eq!(
+            Value::Null,
+            Diagnostic::new("encoding", "bad").to_json()["line"]
+        );
+    }
+}
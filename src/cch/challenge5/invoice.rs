@@ -0,0 +1,469 @@
+use std::{collections::BTreeMap, env, fs, path::Path, sync::Arc};
+
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{de, Deserialize, Deserializer};
+use serde_json::{json, Value};
+
+use super::{
+    order::Order, policy::PolicyState, validate, wants_json, ManifestParams, ParseMode,
+    JSON_MIME_TYPE,
+};
+
+/// Path of the TOML price list; without it there is nothing to invoice.
+const PRICE_LIST_VAR: &str = "CCH_PRICE_LIST";
+
+/// A discount for ordering at least `min_quantity` of an item.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Tier {
+    pub min_quantity: u64,
+    /// Fraction taken off, `0.1` for 10%, at most 1.
+    #[serde(deserialize_with = "deserialize_fraction")]
+    pub discount: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Price {
+    #[serde(deserialize_with = "deserialize_non_negative")]
+    pub unit_price: Decimal,
+    #[serde(default)]
+    pub tiers: Vec<Tier>,
+}
+
+fn deserialize_non_negative<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Decimal, D::Error> {
+    let value = <Decimal as Deserialize>::deserialize(deserializer)?;
+    match value.is_sign_negative() && !value.is_zero() {
+        true => Err(de::Error::custom(format!("{} is negative", value))),
+        false => Ok(value),
+    }
+}
+
+/// A rate between 0 and 1; more would take off more than the whole amount.
+fn deserialize_fraction<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    let value = deserialize_non_negative(deserializer)?;
+    match value > Decimal::ONE {
+        true => Err(de::Error::custom(format!("{} is more than 1", value))),
+        false => Ok(value),
+    }
+}
+
+fn checked_sum(mut amounts: impl Iterator<Item = Decimal>) -> Result<Decimal, InvoiceError> {
+    amounts.try_fold(Decimal::ZERO, |sum, amount| {
+        sum.checked_add(amount).ok_or(InvoiceError::Overflow)
+    })
+}
+
+/// Why orders could not be billed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvoiceError {
+    Unpriced {
+        items: Vec<String>,
+    },
+    /// An amount beyond what a decimal can hold.
+    Overflow,
+}
+impl InvoiceError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unpriced { .. } => "unpriced_items",
+            Self::Overflow => "amount_overflow",
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut body = json!({ "error": self.code(), "message": self.to_string() });
+        if let Self::Unpriced { items } = self {
+            body["items"] = json!(items);
+        }
+        body
+    }
+}
+impl std::fmt::Display for InvoiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unpriced { items } => write!(f, "No price for: {}", items.join(", ")),
+            Self::Overflow => write!(f, "The invoice total is too large"),
+        }
+    }
+}
+
+/// Prices are decimals, written as strings (`"12.50"`) to keep them exact.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PriceList {
+    pub currency: String,
+    /// Digits after the decimal point of every amount.
+    #[serde(default = "PriceList::default_scale")]
+    pub scale: u32,
+    /// Fraction added on top of the discounted total, `0.2` for 20%.
+    #[serde(default, deserialize_with = "deserialize_non_negative")]
+    pub tax_rate: Decimal,
+    pub items: BTreeMap<String, Price>,
+}
+impl PriceList {
+    fn default_scale() -> u32 {
+        2
+    }
+
+    /// Rounds half away from zero to the currency's scale.
+    fn money(&self, amount: Decimal) -> Decimal {
+        let mut amount =
+            amount.round_dp_with_strategy(self.scale, RoundingStrategy::MidpointAwayFromZero);
+        amount.rescale(self.scale);
+        amount
+    }
+
+    /// The price of `item`, ignoring case.
+    fn price(&self, item: &str) -> Option<&Price> {
+        self.items.get(item).or_else(|| {
+            self.items
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(item))
+                .map(|(_, price)| price)
+        })
+    }
+
+    /// Bills `orders`, merging those for the same item so that bulk tiers
+    /// apply to the whole quantity. Fails with the items it has no price
+    /// for, or when an amount gets too large to hold.
+    pub fn invoice(&self, orders: &[Order]) -> Result<Invoice, InvoiceError> {
+        let mut merged: Vec<(&str, u64)> = vec![];
+        for order in orders {
+            match merged
+                .iter_mut()
+                .find(|(item, _)| item.eq_ignore_ascii_case(&order.item))
+            {
+                Some((_, quantity)) => *quantity += u64::from(order.quantity),
+                None => merged.push((&order.item, u64::from(order.quantity))),
+            }
+        }
+        let unpriced: Vec<String> = merged
+            .iter()
+            .filter(|(item, _)| self.price(item).is_none())
+            .map(|(item, _)| item.to_string())
+            .collect();
+        if !unpriced.is_empty() {
+            return Err(InvoiceError::Unpriced { items: unpriced });
+        }
+
+        let lines = merged
+            .into_iter()
+            .map(|(item, quantity)| {
+                let price = self.price(item).unwrap();
+                let amount = price
+                    .unit_price
+                    .checked_mul(Decimal::from(quantity))
+                    .ok_or(InvoiceError::Overflow)?;
+                let amount = self.money(amount);
+                let discount_rate = price
+                    .tiers
+                    .iter()
+                    .filter(|tier| quantity >= tier.min_quantity)
+                    .map(|tier| tier.discount)
+                    .max()
+                    .unwrap_or_default();
+                // Rates are at most 1, so the discount never exceeds the amount.
+                let discount = self.money(amount * discount_rate);
+                Ok(InvoiceLine {
+                    item: item.to_owned(),
+                    quantity,
+                    unit_price: self.money(price.unit_price),
+                    amount,
+                    discount_rate,
+                    discount,
+                    total: amount - discount,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let subtotal = checked_sum(lines.iter().map(|line| line.amount))?;
+        let discount = checked_sum(lines.iter().map(|line| line.discount))?;
+        let net = subtotal - discount;
+        let tax = net
+            .checked_mul(self.tax_rate)
+            .ok_or(InvoiceError::Overflow)?;
+        let tax = self.money(tax);
+        let total = net.checked_add(tax).ok_or(InvoiceError::Overflow)?;
+        Ok(Invoice {
+            currency: self.currency.clone(),
+            lines,
+            subtotal,
+            discount,
+            net,
+            tax_rate: self.tax_rate,
+            tax,
+            total,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvoiceLine {
+    pub item: String,
+    pub quantity: u64,
+    pub unit_price: Decimal,
+    pub amount: Decimal,
+    pub discount_rate: Decimal,
+    pub discount: Decimal,
+    pub total: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invoice {
+    pub currency: String,
+    pub lines: Vec<InvoiceLine>,
+    pub subtotal: Decimal,
+    pub discount: Decimal,
+    pub net: Decimal,
+    pub tax_rate: Decimal,
+    pub tax: Decimal,
+    pub total: Decimal,
+}
+impl Invoice {
+    /// Amounts are strings, as JSON numbers would not stay exact.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "currency": self.currency,
+            "lines": self
+                .lines
+                .iter()
+                .map(|line| json!({
+                    "item": line.item,
+                    "quantity": line.quantity,
+                    "unit_price": line.unit_price.to_string(),
+                    "amount": line.amount.to_string(),
+                    "discount_rate": line.discount_rate.normalize().to_string(),
+                    "discount": line.discount.to_string(),
+                    "total": line.total.to_string(),
+                }))
+                .collect::<Vec<_>>(),
+            "subtotal": self.subtotal.to_string(),
+            "discount": self.discount.to_string(),
+            "net": self.net.to_string(),
+            "tax_rate": self.tax_rate.normalize().to_string(),
+            "tax": self.tax.to_string(),
+            "total": self.total.to_string(),
+        })
+    }
+
+    /// A receipt with amounts right-aligned in one column.
+    pub fn to_text(&self) -> String {
+        let percent = |rate: Decimal| (rate * Decimal::ONE_HUNDRED).normalize();
+        let mut rows: Vec<(String, String)> = vec![];
+        for line in &self.lines {
+            rows.push((
+                format!("{} x {} @ {}", line.quantity, line.item, line.unit_price),
+                line.amount.to_string(),
+            ));
+            if !line.discount.is_zero() {
+                rows.push((
+                    format!("  bulk discount {}%", percent(line.discount_rate)),
+                    format!("-{}", line.discount),
+                ));
+            }
+        }
+        let totals = [
+            ("Subtotal".to_owned(), self.subtotal.to_string()),
+            ("Discounts".to_owned(), format!("-{}", self.discount)),
+            (
+                format!("Tax {}%", percent(self.tax_rate)),
+                self.tax.to_string(),
+            ),
+            (format!("Total {}", self.currency), self.total.to_string()),
+        ];
+        let width = rows
+            .iter()
+            .chain(&totals)
+            .map(|(label, amount)| label.chars().count() + 4 + amount.len())
+            .max()
+            .unwrap_or_default();
+        let row = |(label, amount): &(String, String)| {
+            format!(
+                "{}{:>pad$}",
+                label,
+                amount,
+                pad = width - label.chars().count()
+            )
+        };
+        let mut receipt: Vec<String> = rows.iter().map(row).collect();
+        receipt.push("-".repeat(width));
+        receipt.extend(totals.iter().map(row));
+        receipt.join("\n")
+    }
+}
+
+/// The price list read from `CCH_PRICE_LIST` at startup, if any.
+#[derive(Debug, Default)]
+pub struct Pricing {
+    price_list: Option<PriceList>,
+}
+impl Pricing {
+    pub fn shared_from_env() -> Arc<Self> {
+        let price_list = env::var_os(PRICE_LIST_VAR).and_then(|path| {
+            Self::load(Path::new(&path))
+                .map_err(|e| tracing::warn!(error = %e, "not invoicing orders"))
+                .ok()
+        });
+        Arc::new(Self { price_list })
+    }
+
+    fn load(path: &Path) -> Result<PriceList, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e.message()))
+    }
+}
+
+/// Bills the orders of a manifest, parsed exactly as `/5/manifest` would
+/// with the same `mode`, as a receipt or, if asked for, JSON. Nothing is
+/// booked or reserved.
+pub async fn invoice_manifest(
+    manifest_params: Query<ManifestParams>,
+    Extension(policy_state): Extension<Arc<PolicyState>>,
+    Extension(pricing): Extension<Arc<Pricing>>,
+    headers: HeaderMap,
+    data: Bytes,
+) -> impl IntoResponse {
+    let Some(price_list) = &pricing.price_list else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            HeaderMap::new(),
+            "No price list configured".to_owned(),
+        );
+    };
+    let Some(mode) = ParseMode::parse(manifest_params.mode.as_deref()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            HeaderMap::new(),
+            format!(
+                "Invalid mode, expected one of: {}",
+                ParseMode::NAMES.join(", ")
+            ),
+        );
+    };
+    let json = wants_json(&headers);
+    let validation = validate(mode, &policy_state.current(), None, Some(headers), data);
+    let Some(submission) = validation.submission else {
+        return (validation.status_code, validation.header, validation.body);
+    };
+
+    let mut header = validation.header;
+    let result = price_list.invoice(&submission.orders);
+    let (status_code, body) = match (result, json) {
+        (Ok(invoice), true) => (StatusCode::OK, invoice.to_json().to_string()),
+        (Ok(invoice), false) => (StatusCode::OK, invoice.to_text()),
+        (Err(e), true) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_json().to_string()),
+        (Err(e), false) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
+    };
+    header.remove(CONTENT_TYPE);
+    if json {
+        header.insert(CONTENT_TYPE, HeaderValue::from_static(JSON_MIME_TYPE));
+    }
+    (status_code, header, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRICE_LIST: &str = r#"
+currency = "EUR"
+tax-rate = "0.2"
+
+[items."Toy car"]
+unit-price = "12.5"
+tiers = [
+    { min-quantity = 3, discount = "0.05" },
+    { min-quantity = 10, discount = "0.1" },
+]
+
+[items."Lego brick"]
+unit-price = "0.35"
+"#;
+
+    fn orders(items: &[(&str, u32)]) -> Vec<Order> {
+        items
+            .iter()
+            .map(|(item, quantity)| Order {
+                item: item.to_string(),
+                quantity: *quantity,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_invoice() {
+        let price_list: PriceList = toml::from_str(PRICE_LIST).unwrap();
+        let invoice = price_list
+            .invoice(&orders(&[
+                ("Toy car", 2),
+                ("Lego brick", 23),
+                ("toy car", 1),
+            ]))
+            .unwrap();
+        let json = invoice.to_json();
+        assert_eq!(json!("37.50"), json["lines"][0]["amount"]);
+        assert_eq!(json!("1.88"), json["lines"][0]["discount"]);
+        assert_eq!(json!("8.05"), json["lines"][1]["amount"]);
+        assert_eq!(json!("45.55"), json["subtotal"]);
+        assert_eq!(json!("43.67"), json["net"]);
+        // 8.734 rounds down, and 0.1 + 0.2 stays exact throughout.
+        assert_eq!(json!("8.73"), json["tax"]);
+        assert_eq!(json!("52.40"), json["total"]);
+        assert_eq!(
+            "3 x Toy car @ 12.50      37.50
+  bulk discount 5%       -1.88
+23 x Lego brick @ 0.35    8.05
+------------------------------
+Subtotal                 45.55
+Discounts                -1.88
+Tax 20%                   8.73
+Total EUR                52.40",
+            invoice.to_text()
+        );
+    }
+
+    #[test]
+    fn test_unpriced() {
+        let price_list: PriceList = toml::from_str(PRICE_LIST).unwrap();
+        assert_eq!(
+            Err(InvoiceError::Unpriced {
+                items: vec!["Coal".to_owned()]
+            }),
+            price_list.invoice(&orders(&[("Toy car", 1), ("Coal", 1)]))
+        );
+        assert!(toml::from_str::<PriceList>("currency = \"EUR\"\nitems = {}\nvat = 1").is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let price_list: PriceList = toml::from_str(
+            "currency = \"EUR\"\n[items.Sleigh]\nunit-price = \"79228162514264337593543950\"\n",
+        )
+        .unwrap();
+        let err = price_list
+            .invoice(&orders(&[("Sleigh", 1000), ("Sleigh", u32::MAX)]))
+            .unwrap_err();
+        assert_eq!("amount_overflow", err.code());
+
+        let parses = |tax_rate: &str, discount: &str| {
+            toml::from_str::<PriceList>(&format!(
+                "currency = \"EUR\"\ntax-rate = \"{}\"\n[items.x]\nunit-price = \"1\"\ntiers = [{{ min-quantity = 2, discount = \"{}\" }}]",
+                tax_rate, discount
+            ))
+            .is_ok()
+        };
+        assert!(parses("0.2", "1"));
+        assert!(!parses("-0.2", "0.5"));
+        assert!(!parses("0.2", "1.5"));
+        assert!(!parses("0.2", "-0.1"));
+    }
+}
@@ -0,0 +1,1007 @@
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::IntoResponse,
+    Extension,
+};
+use cargo_manifest::Manifest;
+use serde::Deserialize;
+use serde_json::{self, Value};
+use std::sync::Arc;
+
+use aggregate::{Aggregation, Output};
+use book::{NewSubmission, OrderBook, Provenance};
+use catalog::Catalog;
+use diagnostic::Diagnostic;
+use order::{Order, Orders};
+use policy::{Policy, PolicyState};
+
+mod aggregate;
+pub mod book;
+pub mod catalog;
+pub mod convert;
+mod diagnostic;
+pub mod invoice;
+mod order;
+pub mod policy;
+
+const TOML_MIME_TYPE: &str = "application/toml";
+const JSON_MIME_TYPE: &str = "application/json";
+const YAML_MIME_TYPE: &str = "application/yaml";
+/// Charsets a body may declare; all of them are read as UTF-8.
+const UTF8_CHARSETS: &[&str] = &["utf-8", "utf8", "us-ascii"];
+/// Response header naming the format a sniffed body was read as.
+const DETECTED_FORMAT_HEADER: &str = "x-detected-format";
+/// Response header carrying the ID the accepted orders were booked under.
+const ORDER_ID_HEADER: &str = "x-order-id";
+
+/// A Cargo manifest whose `package.metadata` is kept as loose JSON, which can
+/// hold whatever any of the three formats put there.
+type CargoManifest = Manifest<Value>;
+
+#[derive(Debug, Default, PartialEq)]
+struct Validation {
+    status_code: StatusCode,
+    header: HeaderMap,
+    body: String,
+    /// Orders to book, when the manifest had any worth keeping.
+    submission: Option<NewSubmission>,
+}
+impl Validation {
+    fn status(status_code: StatusCode) -> Self {
+        Self {
+            status_code,
+            ..Self::default()
+        }
+    }
+
+    /// A `400` explaining `diagnostic`, as JSON if the client asked for it.
+    fn invalid_manifest(diagnostic: Diagnostic, json: bool) -> Self {
+        if json {
+            return Self::json(StatusCode::BAD_REQUEST, diagnostic.to_json());
+        }
+        Self {
+            status_code: StatusCode::BAD_REQUEST,
+            body: diagnostic.to_text(),
+            ..Self::default()
+        }
+    }
+
+    fn json(status_code: StatusCode, body: Value) -> Self {
+        let mut header = HeaderMap::new();
+        header.insert(CONTENT_TYPE, HeaderValue::from_static(JSON_MIME_TYPE));
+        Self {
+            status_code,
+            header,
+            body: body.to_string(),
+            ..Self::default()
+        }
+    }
+}
+
+/// How the declared Content-Type and the body decide which parser runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum ParseMode {
+    /// TOML, then JSON, then YAML, whatever the header said.
+    #[default]
+    Legacy,
+    /// Only the parser of the declared format.
+    Strict,
+    /// The declared format first, then the others, reporting which one won.
+    Sniff,
+}
+impl ParseMode {
+    const NAMES: &'static [&'static str] = &["legacy", "strict", "sniff"];
+
+    fn parse(value: Option<&str>) -> Option<Self> {
+        match value.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("legacy") => Some(Self::Legacy),
+            Some("strict") => Some(Self::Strict),
+            Some("sniff") => Some(Self::Sniff),
+            _ => None,
+        }
+    }
+
+    /// The formats to try on a body declared as `declared`, in order.
+    fn candidates(self, declared: Format) -> Vec<Format> {
+        match self {
+            Self::Legacy => Format::ALL.to_vec(),
+            Self::Strict => vec![declared],
+            Self::Sniff => {
+                let mut candidates = vec![declared];
+                candidates.extend(Format::ALL.into_iter().filter(|&format| format != declared));
+                candidates
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+impl Format {
+    /// The order in which formats are tried on a body.
+    const ALL: [Self; 3] = [Self::Toml, Self::Json, Self::Yaml];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Toml => "toml",
+            Self::Json => "json",
+            Self::Yaml => "yaml",
+        }
+    }
+
+    fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type {
+            TOML_MIME_TYPE => Some(Self::Toml),
+            JSON_MIME_TYPE => Some(Self::Json),
+            YAML_MIME_TYPE => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Reads a Content-Type such as `application/toml; charset=utf-8` or
+    /// `application/vnd.santa+json`. A charset other than UTF-8 makes the
+    /// type unsupported, as bodies are only ever decoded as UTF-8.
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        let mut parts = content_type.split(';');
+        let essence = parts.next()?.trim().to_ascii_lowercase();
+        for param in parts {
+            let Some((name, value)) = param.split_once('=') else {
+                continue;
+            };
+            let charset = value.trim().trim_matches('"');
+            if name.trim().eq_ignore_ascii_case("charset")
+                && !UTF8_CHARSETS
+                    .iter()
+                    .any(|utf8| utf8.eq_ignore_ascii_case(charset))
+            {
+                return None;
+            }
+        }
+        if let Some(format) = Self::from_mime_type(&essence) {
+            return Some(format);
+        }
+        let (_, subtype) = essence.split_once('/')?;
+        Self::ALL.into_iter().find(|format| {
+            subtype.rsplit_once('+').map(|(_, suffix)| suffix) == Some(format.name())
+        })
+    }
+
+    /// Whether `text` is syntactically a document of this format at all.
+    fn accepts(self, text: &str) -> bool {
+        match self {
+            Self::Toml => text.parse::<toml::Table>().is_ok(),
+            Self::Json => serde_json::from_str::<Value>(text).is_ok(),
+            Self::Yaml => serde_yaml::from_str::<serde_yaml::Value>(text).is_ok(),
+        }
+    }
+
+    fn parse_manifest(self, text: &str) -> Result<CargoManifest, Diagnostic> {
+        let syntax = !self.accepts(text);
+        match self {
+            Self::Toml => toml::from_str(text).map_err(|e| Diagnostic::from_toml(text, syntax, e)),
+            Self::Json => serde_json::from_str(text).map_err(|e| Diagnostic::from_json(text, e)),
+            Self::Yaml => {
+                serde_yaml::from_str(text).map_err(|e| Diagnostic::from_yaml(text, syntax, e))
+            }
+        }
+        .map_err(|diagnostic| diagnostic.with_format(self))
+    }
+}
+
+/// Whether the `Accept` header asks for JSON rather than plain text.
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|range| {
+            range
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        })
+        .any(|essence| essence == JSON_MIME_TYPE || essence.ends_with("+json"))
+}
+
+/// The raw `package.metadata.orders` array, if the manifest has one.
+fn order_values(manifest: &CargoManifest) -> Option<&Vec<Value>> {
+    manifest
+        .package
+        .as_ref()?
+        .metadata
+        .as_ref()?
+        .get("orders")?
+        .as_array()
+}
+
+fn validate(
+    mode: ParseMode,
+    policy: &Policy,
+    aggregation: Option<&Aggregation>,
+    headers: Option<HeaderMap>,
+    data: Bytes,
+) -> Validation {
+    let headers = headers.unwrap_or_default();
+    let Some(header_type) = headers.get(CONTENT_TYPE) else {
+        return Validation::status(StatusCode::NO_CONTENT);
+    };
+    let Some(declared) = Format::from_content_type(header_type.to_str().unwrap_or_default()) else {
+        return Validation::status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    };
+    let json = wants_json(&headers);
+    let utf8_str = match std::str::from_utf8(data.as_ref()) {
+        Ok(utf8_str) => utf8_str,
+        Err(e) => {
+            return Validation::invalid_manifest(Diagnostic::new("encoding", e.to_string()), json)
+        }
+    };
+
+    // The first candidate format that can read the body is used, and the
+    // whole document must then be a valid Cargo manifest. When none can,
+    // the first candidate explains why.
+    let candidates = mode.candidates(declared);
+    let format = candidates
+        .iter()
+        .copied()
+        .find(|format| format.accepts(utf8_str))
+        .unwrap_or(candidates[0]);
+    let manifest = match format.parse_manifest(utf8_str) {
+        Ok(manifest) => manifest,
+        Err(diagnostic) => return Validation::invalid_manifest(diagnostic, json),
+    };
+    let mut header = HeaderMap::new();
+    if mode == ParseMode::Sniff {
+        header.insert(
+            DETECTED_FORMAT_HEADER,
+            HeaderValue::from_static(format.name()),
+        );
+    }
+
+    let order_count = order_values(&manifest).map_or(0, Vec::len);
+    if let Err(violation) = policy.check(&manifest, order_count) {
+        let mut validation = match json {
+            true => Validation::json(StatusCode::BAD_REQUEST, violation.to_json()),
+            false => Validation {
+                status_code: StatusCode::BAD_REQUEST,
+                body: violation.message,
+                ..Validation::default()
+            },
+        };
+        validation.header.extend(header);
+        return validation;
+    }
+
+    let Some(order_values) = order_values(&manifest) else {
+        if json {
+            let diagnostic = Diagnostic::new("data", "package.metadata.orders is missing");
+            let mut validation = Validation::json(
+                StatusCode::BAD_REQUEST,
+                diagnostic.with_format(format).to_json(),
+            );
+            validation.header.extend(header);
+            return validation;
+        }
+        return Validation {
+            status_code: StatusCode::BAD_REQUEST,
+            header,
+            ..Validation::default()
+        };
+    };
+    let reviewed: Vec<_> = order_values.iter().map(Order::try_from).collect();
+    let accepted: Vec<Order> = reviewed
+        .iter()
+        .filter_map(|order| order.clone().ok())
+        .collect();
+    let submission = match (&manifest.package, accepted.is_empty()) {
+        (Some(package), false) => Some(NewSubmission {
+            provenance: Provenance::new(
+                package.name.clone(),
+                package
+                    .version
+                    .as_ref()
+                    .and_then(|version| version.as_ref().as_local())
+                    .cloned(),
+                format.name(),
+                &data,
+            ),
+            orders: accepted.clone(),
+            reserved: false,
+        }),
+        _ => None,
+    };
+    if let Some(aggregation) = aggregation.filter(|_| !accepted.is_empty()) {
+        let summary = aggregation.aggregate(&accepted);
+        let (mime_type, body) = match Output::from_accept(&headers) {
+            Output::Text => ("text/plain; charset=utf-8", summary.to_text()),
+            Output::Json => (JSON_MIME_TYPE, summary.to_json().to_string()),
+            Output::Csv => (aggregate::CSV_MIME_TYPE, summary.to_csv()),
+        };
+        header.insert(CONTENT_TYPE, HeaderValue::from_static(mime_type));
+        return Validation {
+            status_code: StatusCode::OK,
+            header,
+            body,
+            submission,
+        };
+    }
+    if json {
+        // 422 when there were orders but none of them could be taken.
+        let status_code = match accepted.is_empty() {
+            true if !reviewed.is_empty() => StatusCode::UNPROCESSABLE_ENTITY,
+            _ => StatusCode::OK,
+        };
+        let mut validation = Validation::json(status_code, order::report(&reviewed));
+        validation.header.extend(header);
+        validation.submission = submission;
+        return validation;
+    }
+    if accepted.is_empty() {
+        return Validation {
+            status_code: StatusCode::NO_CONTENT,
+            header,
+            ..Validation::default()
+        };
+    }
+    Validation {
+        status_code: StatusCode::OK,
+        header,
+        body: Orders(accepted).to_string(),
+        submission,
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ManifestParams {
+    mode: Option<String>,
+    aggregate: Option<bool>,
+    normalize: Option<String>,
+    sort: Option<String>,
+}
+
+/// Lists the orders of a Cargo manifest sent as TOML, JSON or YAML. `mode`
+/// picks how the body's format is decided: `legacy` (the default), `strict`
+/// or `sniff`. With `aggregate=true` duplicate items are merged, following
+/// `normalize` and `sort`, and totals appended. Accepted orders take their
+/// stock from the catalog, all or none, and are booked with their ID
+/// returned in `x-order-id`.
+pub async fn manifest_messaging(
+    manifest_params: Query<ManifestParams>,
+    Extension(policy_state): Extension<Arc<PolicyState>>,
+    Extension(order_book): Extension<Arc<OrderBook>>,
+    Extension(catalog): Extension<Arc<Catalog>>,
+    headers: HeaderMap,
+    data: Bytes,
+) -> impl IntoResponse {
+    let Some(mode) = ParseMode::parse(manifest_params.mode.as_deref()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            HeaderMap::new(),
+            format!(
+                "Invalid mode, expected one of: {}",
+                ParseMode::NAMES.join(", ")
+            ),
+        );
+    };
+    let aggregation = match manifest_params.aggregate {
+        Some(true) => match Aggregation::from_params(
+            manifest_params.normalize.as_deref(),
+            manifest_params.sort.as_deref(),
+        ) {
+            Ok(aggregation) => Some(aggregation),
+            Err(message) => return (StatusCode::BAD_REQUEST, HeaderMap::new(), message),
+        },
+        _ => None,
+    };
+    let json = wants_json(&headers);
+    let mut validation = validate(
+        mode,
+        &policy_state.current(),
+        aggregation.as_ref(),
+        Some(headers),
+        data,
+    );
+    if let Some(mut submission) = validation.submission.take() {
+        submission.reserved = match catalog.exchange(&[], &submission.orders) {
+            Ok(reserved) => reserved,
+            Err(shortages) => {
+                let (status_code, body) = catalog::shortage_report(&shortages, json);
+                let mut header = HeaderMap::new();
+                if json {
+                    header.insert(CONTENT_TYPE, HeaderValue::from_static(JSON_MIME_TYPE));
+                }
+                return (status_code, header, body);
+            }
+        };
+        let reserved = submission.reserved.then(|| submission.orders.clone());
+        match order_book.submit(submission) {
+            Ok(submission) => {
+                validation
+                    .header
+                    .insert(ORDER_ID_HEADER, HeaderValue::from(submission.id));
+            }
+            Err(e) => {
+                if let Some(orders) = reserved {
+                    let _ = catalog.exchange(&orders, &[]);
+                }
+                let failure = match json {
+                    true => Validation::json(e.status_code(), e.to_json()),
+                    false => Validation {
+                        status_code: e.status_code(),
+                        body: e.to_string(),
+                        ..Validation::default()
+                    },
+                };
+                return (failure.status_code, failure.header, failure.body);
+            }
+        }
+    }
+    (validation.status_code, validation.header, validation.body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const HTML_MIME_TYPE: &str = "text/html";
+
+    /// The default policy without the magic keyword, for bodies that only
+    /// exercise parsing.
+    fn no_policy() -> Policy {
+        Policy {
+            required_keywords: vec![],
+            ..Policy::default()
+        }
+    }
+
+    fn header_content_type(content_type: &str) -> Option<HeaderMap> {
+        let mut hm = HeaderMap::new();
+        hm.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        Some(hm)
+    }
+
+    #[test]
+    fn test_task1_good() {
+        let data = b"
+[package]
+name = \"not-a-gift-order\"
+authors = [\"Not Santa\"]
+keywords = [\"Christmas 2024\"]
+
+[[package.metadata.orders]]
+item = \"Toy car\"
+quantity = 2
+
+[[package.metadata.orders]]
+item = \"Lego brick\"
+quantity = 23
+";
+        let validated = validate(
+            ParseMode::Legacy,
+            &Policy::default(),
+            None,
+            header_content_type(TOML_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::OK, validated.status_code);
+        assert_eq!("Toy car: 2\nLego brick: 23", validated.body);
+        let submission = validated.submission.unwrap();
+        assert_eq!(2, submission.orders.len());
+        assert_eq!("toml", submission.provenance.format);
+    }
+
+    #[tokio::test]
+    async fn test_default_catalog() {
+        let data = b"
+[package]
+name = \"not-a-gift-order\"
+authors = [\"Not Santa\"]
+keywords = [\"Christmas 2024\"]
+
+[[package.metadata.orders]]
+item = \"Toy car\"
+quantity = 2
+
+[[package.metadata.orders]]
+item = \"Lego brick\"
+quantity = 23
+";
+        // Without a configured catalog the orders are booked as they come.
+        let response = manifest_messaging(
+            Query(ManifestParams::default()),
+            Extension(Arc::new(PolicyState::default())),
+            Extension(Arc::new(
+                OrderBook::new(Box::<book::MemoryStore>::default()),
+            )),
+            Extension(Arc::new(Catalog::default())),
+            header_content_type(TOML_MIME_TYPE).unwrap(),
+            Bytes::from_static(data),
+        )
+        .await
+        .into_response();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("1", response.headers()[ORDER_ID_HEADER]);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&b"Toy car: 2\nLego brick: 23"[..], body);
+    }
+
+    #[test]
+    fn test_task1_bad() {
+        let data = b"
+[package]
+name = \"coal-in-a-bowl\"
+authors = [\"H4CK3R_13E7\"]
+keywords = [\"Christmas 2024\"]
+
+[[package.metadata.orders]]
+item = \"Coal\"
+quantity = \"Hahaha get rekt\"
+";
+        assert_eq!(
+            StatusCode::NO_CONTENT,
+            validate(
+                ParseMode::Legacy,
+                &Policy::default(),
+                None,
+                header_content_type(TOML_MIME_TYPE),
+                Bytes::from_static(data)
+            )
+            .status_code
+        );
+    }
+
+    #[test]
+    fn test_task2_fail1() {
+        let data = b"
+[package]
+name = false
+authors = [\"Not Santa\"]
+keywords = [\"Christmas 2024\"]
+";
+        let validated = validate(
+            ParseMode::Legacy,
+            &Policy::default(),
+            None,
+            header_content_type(TOML_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, validated.status_code);
+        assert!(validated.body.starts_with("Invalid manifest: invalid type"));
+    }
+
+    #[test]
+    fn test_task2_fail2() {
+        let data = b"
+[package]
+name = \"not-a-gift-order\"
+authors = [\"Not Santa\"]
+keywords = [\"Christmas 2024\"]
+
+[profile.release]
+incremental = \"stonks\"
+";
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            validate(
+                ParseMode::Legacy,
+                &Policy::default(),
+                None,
+                header_content_type(TOML_MIME_TYPE),
+                Bytes::from_static(data)
+            )
+            .status_code
+        );
+    }
+
+    #[test]
+    fn test_task3_fail1() {
+        let data = b"
+[package]
+name = \"grass\"
+authors = [\"A vegan cow\"]
+keywords = [\"Moooooo\"]
+";
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            validate(
+                ParseMode::Legacy,
+                &Policy::default(),
+                None,
+                header_content_type(TOML_MIME_TYPE),
+                Bytes::from_static(data)
+            )
+            .status_code
+        );
+    }
+
+    #[test]
+    fn test_task4_html_bad() {
+        let data = b"<h1>Hello, bird!</h1>";
+        assert_eq!(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            validate(
+                ParseMode::Legacy,
+                &Policy::default(),
+                None,
+                header_content_type(HTML_MIME_TYPE),
+                Bytes::from_static(data)
+            )
+            .status_code
+        );
+    }
+
+    #[test]
+    fn test_task4_yaml_good() {
+        let data = b"
+package:
+  name: big-chungus-sleigh
+  version: \"2.0.24\"
+  metadata:
+    orders:
+      - item: \"Toy train\"
+        quantity: 5
+  rust-version: \"1.69\"
+  keywords:
+    - \"Christmas 2024\"
+";
+        assert_eq!(
+            StatusCode::OK,
+            validate(
+                ParseMode::Legacy,
+                &Policy::default(),
+                None,
+                header_content_type(TOML_MIME_TYPE),
+                Bytes::from_static(data)
+            )
+            .status_code
+        );
+    }
+
+    #[test]
+    fn test_task4_json_good() {
+        let data = b"
+{
+  \"package\": {
+    \"name\": \"big-chungus-sleigh\",
+    \"version\": \"2.0.24\",
+    \"metadata\": {
+      \"orders\": [
+        {
+          \"item\": \"Toy train\",
+          \"quantity\": 5
+        }
+      ]
+    },
+    \"rust-version\": \"1.69\",
+    \"keywords\": [
+      \"Christmas 2024\"
+    ]
+  }
+}
+";
+        assert_eq!(
+            StatusCode::OK,
+            validate(
+                ParseMode::Legacy,
+                &Policy::default(),
+                None,
+                header_content_type(TOML_MIME_TYPE),
+                Bytes::from_static(data)
+            )
+            .status_code
+        );
+    }
+
+    #[test]
+    fn test_invalid_manifest_every_format() {
+        let data = b"{\"package\": {\"name\": \"x\", \"metadata\": {\"orders\": []}}, \"profile\": {\"release\": {\"incremental\": \"stonks\"}}}";
+        let validated = validate(
+            ParseMode::Legacy,
+            &no_policy(),
+            None,
+            header_content_type(JSON_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, validated.status_code);
+        assert!(validated.body.starts_with("Invalid manifest: "));
+
+        let data = b"
+package:
+  name: [not, a, name]
+  metadata:
+    orders:
+      - item: Toy train
+        quantity: 5
+";
+        let validated = validate(
+            ParseMode::Legacy,
+            &no_policy(),
+            None,
+            header_content_type(YAML_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, validated.status_code);
+        assert!(validated.body.starts_with("Invalid manifest: "));
+
+        let validated = validate(
+            ParseMode::Legacy,
+            &no_policy(),
+            None,
+            header_content_type(YAML_MIME_TYPE),
+            Bytes::from_static(b"just some words"),
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, validated.status_code);
+    }
+
+    #[test]
+    fn test_content_type_params() {
+        let data = b"[package]\nname = \"x\"\n\n[[package.metadata.orders]]\nitem = \"Toy car\"\nquantity = 2\n";
+        for content_type in [
+            "application/toml; charset=utf-8",
+            "Application/TOML;charset=\"UTF-8\"",
+            "application/vnd.santa+toml",
+        ] {
+            let validated = validate(
+                ParseMode::Strict,
+                &no_policy(),
+                None,
+                header_content_type(content_type),
+                Bytes::from_static(data),
+            );
+            assert_eq!(StatusCode::OK, validated.status_code, "{}", content_type);
+        }
+        assert_eq!(
+            Some(Format::Json),
+            Format::from_content_type("application/problem+json")
+        );
+        assert_eq!(
+            Some(Format::Yaml),
+            Format::from_content_type("text/x.santa+yaml; q=1")
+        );
+        assert_eq!(
+            None,
+            Format::from_content_type("application/toml; charset=latin1")
+        );
+        assert_eq!(None, Format::from_content_type("application/json-seq"));
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let data = b"
+package:
+  name: big-chungus-sleigh
+  metadata:
+    orders:
+      - item: \"Toy train\"
+        quantity: 5
+";
+        let validated = validate(
+            ParseMode::Strict,
+            &no_policy(),
+            None,
+            header_content_type(TOML_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, validated.status_code);
+        assert!(validated.body.starts_with("Invalid manifest: "));
+        let validated = validate(
+            ParseMode::Strict,
+            &no_policy(),
+            None,
+            header_content_type(YAML_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::OK, validated.status_code);
+        assert!(validated.header.get(DETECTED_FORMAT_HEADER).is_none());
+    }
+
+    #[test]
+    fn test_sniff_mode() {
+        let data = b"{\"package\": {\"name\": \"x\", \"metadata\": {\"orders\": [{\"item\": \"Toy train\", \"quantity\": 5}]}}}";
+        // JSON is also YAML, but the declared format gets the first try.
+        let validated = validate(
+            ParseMode::Sniff,
+            &no_policy(),
+            None,
+            header_content_type(YAML_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!("yaml", validated.header[DETECTED_FORMAT_HEADER]);
+        let validated = validate(
+            ParseMode::Sniff,
+            &no_policy(),
+            None,
+            header_content_type(TOML_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::OK, validated.status_code);
+        assert_eq!("json", validated.header[DETECTED_FORMAT_HEADER]);
+        assert_eq!(Some(ParseMode::Legacy), ParseMode::parse(None));
+        assert_eq!(None, ParseMode::parse(Some("lenient")));
+    }
+
+    #[test]
+    fn test_diagnostics_as_json() {
+        let data = b"[package]\nname = false\n";
+        let mut headers = header_content_type(TOML_MIME_TYPE).unwrap();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/html, application/json;q=0.9"),
+        );
+        let validated = validate(
+            ParseMode::Strict,
+            &no_policy(),
+            None,
+            Some(headers),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, validated.status_code);
+        assert_eq!(JSON_MIME_TYPE, validated.header[CONTENT_TYPE]);
+        let report: Value = serde_json::from_str(&validated.body).unwrap();
+        assert_eq!("data", report["kind"]);
+        assert_eq!("toml", report["format"]);
+        assert_eq!(2, report["line"]);
+        assert_eq!(8, report["column"]);
+
+        let validated = validate(
+            ParseMode::Strict,
+            &no_policy(),
+            None,
+            header_content_type(TOML_MIME_TYPE),
+            Bytes::from_static(b"[package\n"),
+        );
+        assert!(validated.body.starts_with("Invalid manifest: "));
+        assert!(validated.body.contains(" --> line 1, column 9"));
+    }
+
+    #[test]
+    fn test_order_report_every_format() {
+        let bodies: [(&str, &[u8]); 3] = [
+            (
+                TOML_MIME_TYPE,
+                b"[package]\nname = \"x\"\n\n[[package.metadata.orders]]\nitem = \"Toy car\"\nquantity = 5000000000\n\n[[package.metadata.orders]]\nitem = \"Coal\"\nquantity = -1\n",
+            ),
+            (
+                JSON_MIME_TYPE,
+                b"{\"package\": {\"name\": \"x\", \"metadata\": {\"orders\": [{\"item\": \"Toy car\", \"quantity\": 5000000000}, {\"item\": \"Coal\", \"quantity\": -1}]}}}",
+            ),
+            (
+                YAML_MIME_TYPE,
+                b"package:\n  name: x\n  metadata:\n    orders:\n      - item: Toy car\n        quantity: 5000000000\n      - item: Coal\n        quantity: -1\n",
+            ),
+        ];
+        for (content_type, data) in bodies {
+            let mut headers = header_content_type(content_type).unwrap();
+            headers.insert(ACCEPT, HeaderValue::from_static(JSON_MIME_TYPE));
+            let validated = validate(
+                ParseMode::Strict,
+                &no_policy(),
+                None,
+                Some(headers),
+                Bytes::from_static(data),
+            );
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, validated.status_code);
+            let report: Value = serde_json::from_str(&validated.body).unwrap();
+            assert_eq!(
+                "quantity_out_of_range", report["orders"][0]["reason"],
+                "{}",
+                content_type
+            );
+            assert_eq!(
+                "negative_quantity", report["orders"][1]["reason"],
+                "{}",
+                content_type
+            );
+
+            // Out of range quantities are no longer truncated into valid orders.
+            let validated = validate(
+                ParseMode::Strict,
+                &no_policy(),
+                None,
+                header_content_type(content_type),
+                Bytes::from_static(data),
+            );
+            assert_eq!(StatusCode::NO_CONTENT, validated.status_code);
+        }
+    }
+
+    #[test]
+    fn test_policy_violation() {
+        let data = b"
+[package]
+name = \"grass\"
+authors = [\"A vegan cow\"]
+keywords = [\"Moooooo\"]
+
+[[package.metadata.orders]]
+item = \"Grass\"
+quantity = 1
+";
+        let validated = validate(
+            ParseMode::Legacy,
+            &Policy::default(),
+            None,
+            header_content_type(TOML_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, validated.status_code);
+        assert_eq!("Magic keyword not provided", validated.body);
+
+        let mut headers = header_content_type(TOML_MIME_TYPE).unwrap();
+        headers.insert(ACCEPT, HeaderValue::from_static(JSON_MIME_TYPE));
+        let policy = Policy {
+            max_orders: Some(0),
+            ..no_policy()
+        };
+        let validated = validate(
+            ParseMode::Legacy,
+            &policy,
+            None,
+            Some(headers),
+            Bytes::from_static(data),
+        );
+        let report: Value = serde_json::from_str(&validated.body).unwrap();
+        assert_eq!("too_many_orders", report["code"]);
+    }
+
+    #[test]
+    fn test_aggregation() {
+        let data = b"
+[package]
+name = \"not-a-gift-order\"
+keywords = [\"Christmas 2024\"]
+
+[[package.metadata.orders]]
+item = \"Toy car\"
+quantity = 2
+
+[[package.metadata.orders]]
+item = \"Lego brick\"
+quantity = 23
+
+[[package.metadata.orders]]
+item = \"toy  car\"
+quantity = 3
+";
+        let aggregation =
+            Aggregation::from_params(Some("case,whitespace"), Some("quantity")).unwrap();
+        let validated = validate(
+            ParseMode::Strict,
+            &Policy::default(),
+            Some(&aggregation),
+            header_content_type(TOML_MIME_TYPE),
+            Bytes::from_static(data),
+        );
+        assert_eq!(StatusCode::OK, validated.status_code);
+        assert_eq!(
+            "Lego brick: 23\nToy car: 5\nTotal: 28 across 2 items",
+            validated.body
+        );
+
+        let mut headers = header_content_type(TOML_MIME_TYPE).unwrap();
+        headers.insert(ACCEPT, HeaderValue::from_static("text/csv"));
+        let validated = validate(
+            ParseMode::Strict,
+            &Policy::default(),
+            Some(&aggregation),
+            Some(headers),
+            Bytes::from_static(data),
+        );
+        assert_eq!("text/csv", validated.header[CONTENT_TYPE]);
+        assert!(validated.body.ends_with("TOTAL,28,3\r\n"));
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// The only keys an order may have.
+const ORDER_FIELDS: &[&str] = &["item", "quantity"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Order {
+    pub item: String,
+    pub quantity: u32,
+}
+
+/// Why an entry of `package.metadata.orders` was turned down.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderError {
+    NotATable,
+    UnknownField(String),
+    MissingItem,
+    InvalidItem,
+    MissingQuantity,
+    NonIntegerQuantity,
+    NegativeQuantity,
+    QuantityOutOfRange,
+}
+impl OrderError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotATable => "not_a_table",
+            Self::UnknownField(_) => "unknown_field",
+            Self::MissingItem => "missing_item",
+            Self::InvalidItem => "invalid_item",
+            Self::MissingQuantity => "missing_quantity",
+            Self::NonIntegerQuantity => "non_integer_quantity",
+            Self::NegativeQuantity => "negative_quantity",
+            Self::QuantityOutOfRange => "quantity_out_of_range",
+        }
+    }
+}
+impl Display for OrderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotATable => write!(f, "an order must be a table"),
+            Self::UnknownField(field) => write!(f, "unknown field `{}`", field),
+            Self::MissingItem => write!(f, "`item` is missing"),
+            Self::InvalidItem => write!(f, "`item` must be a string"),
+            Self::MissingQuantity => write!(f, "`quantity` is missing"),
+            Self::NonIntegerQuantity => write!(f, "`quantity` must be an integer"),
+            Self::NegativeQuantity => write!(f, "`quantity` must not be negative"),
+            Self::QuantityOutOfRange => {
+                write!(f, "`quantity` must be at most {}", u32::MAX)
+            }
+        }
+    }
+}
+
+/// Reads a quantity, which TOML, JSON and YAML all hand over as a JSON
+/// number by now. Integers too large for `u64` arrive as floats.
+fn quantity(value: &Value) -> Result<u32, OrderError> {
+    if let Some(quantity) = value.as_u64() {
+        return u32::try_from(quantity).map_err(|_| OrderError::QuantityOutOfRange);
+    }
+    if value.as_i64().is_some() {
+        return Err(OrderError::NegativeQuantity);
+    }
+    match value.as_f64() {
+        Some(quantity) if quantity.fract() == 0.0 && quantity >= u64::MAX as f64 => {
+            Err(OrderError::QuantityOutOfRange)
+        }
+        Some(quantity) if quantity.fract() == 0.0 && quantity <= i64::MIN as f64 => {
+            Err(OrderError::NegativeQuantity)
+        }
+        _ => Err(OrderError::NonIntegerQuantity),
+    }
+}
+
+impl TryFrom<&Value> for Order {
+    type Error = OrderError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        let value = value.as_object().ok_or(OrderError::NotATable)?;
+        if let Some(field) = value
+            .keys()
+            .find(|key| !ORDER_FIELDS.contains(&key.as_str()))
+        {
+            return Err(OrderError::UnknownField(field.to_owned()));
+        }
+        let item = value
+            .get("item")
+            .ok_or(OrderError::MissingItem)?
+            .as_str()
+            .ok_or(OrderError::InvalidItem)?
+            .to_owned();
+        let quantity = quantity(value.get("quantity").ok_or(OrderError::MissingQuantity)?)?;
+        Ok(Self { item, quantity })
+    }
+}
+impl Display for Order {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.item, self.quantity)
+    }
+}
+pub struct Orders(pub Vec<Order>);
+impl Display for Orders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut comma_separated = String::new();
+
+        for num in &self.0[0..self.0.len() - 1] {
+            comma_separated.push_str(&num.to_string());
+            comma_separated.push('\n');
+        }
+
+        comma_separated.push_str(&self.0[self.0.len() - 1].to_string());
+        write!(f, "{}", comma_separated)
+    }
+}
+
+/// Every order with whether it was taken, and why not when it was not.
+pub fn report(reviewed: &[Result<Order, OrderError>]) -> Value {
+    let orders: Vec<Value> = reviewed
+        .iter()
+        .enumerate()
+        .map(|(index, order)| match order {
+            Ok(order) => json!({
+                "index": index,
+                "status": "accepted",
+                "item": order.item,
+                "quantity": order.quantity,
+            }),
+            Err(e) => json!({
+                "index": index,
+                "status": "rejected",
+                "reason": e.code(),
+                "message": e.to_string(),
+            }),
+        })
+        .collect();
+    let accepted = reviewed.iter().filter(|order| order.is_ok()).count();
+    json!({
+        "accepted": accepted,
+        "rejected": reviewed.len() - accepted,
+        "orders": orders,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn review(order: Value) -> Result<Order, OrderError> {
+        Order::try_from(&order)
+    }
+
+    #[test]
+    fn test_rejection_reasons() {
+        assert_eq!(
+            Ok(Order {
+                item: "Toy car".to_owned(),
+                quantity: u32::MAX
+            }),
+            review(json!({ "item": "Toy car", "quantity": 4294967295u64 }))
+        );
+        assert_eq!(Err(OrderError::NotATable), review(json!(["Toy car", 2])));
+        assert_eq!(
+            Err(OrderError::UnknownField("colour".to_owned())),
+            review(json!({ "item": "Toy car", "quantity": 2, "colour": "red" }))
+        );
+        assert_eq!(
+            Err(OrderError::MissingItem),
+            review(json!({ "quantity": 2 }))
+        );
+        assert_eq!(
+            Err(OrderError::InvalidItem),
+            review(json!({ "item": 7, "quantity": 2 }))
+        );
+        assert_eq!(
+            Err(OrderError::MissingQuantity),
+            review(json!({ "item": "Coal" }))
+        );
+        for quantity in [
+            json!("Hahaha get rekt"),
+            json!(2.5),
+            json!(2.0),
+            json!(null),
+        ] {
+            assert_eq!(
+                Err(OrderError::NonIntegerQuantity),
+                review(json!({ "item": "Coal", "quantity": quantity }))
+            );
+        }
+        assert_eq!(
+            Err(OrderError::NegativeQuantity),
+            review(json!({ "item": "Coal", "quantity": -1 }))
+        );
+        for quantity in [json!(4294967296u64), json!(1e30)] {
+            assert_eq!(
+                Err(OrderError::QuantityOutOfRange),
+                review(json!({ "item": "Coal", "quantity": quantity }))
+            );
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let reviewed = vec![
+            review(json!({ "item": "Toy car", "quantity": 2 })),
+            review(json!({ "item": "Coal", "quantity": -3 })),
+        ];
+        let report = report(&reviewed);
+        assert_eq!(1, report["accepted"]);
+        assert_eq!(1, report["rejected"]);
+        assert_eq!("accepted", report["orders"][0]["status"]);
+        assert_eq!("negative_quantity", report["orders"][1]["reason"]);
+        assert_eq!(1, report["orders"][1]["index"]);
+    }
+}
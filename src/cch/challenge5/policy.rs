@@ -0,0 +1,353 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use cargo_manifest::MaybeInherited;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::{json, Value};
+
+use super::CargoManifest;
+
+/// Path of a TOML file holding the policy; it is re-read whenever it changes.
+const POLICY_VAR: &str = "CCH_MANIFEST_POLICY";
+/// Seconds between checks of the file's modification time.
+const RELOAD_INTERVAL_VAR: &str = "CCH_MANIFEST_POLICY_RELOAD_SECS";
+const DEFAULT_RELOAD_INTERVAL: u64 = 10;
+const MAGIC_KEYWORD: &str = "Christmas 2024";
+
+/// Rules a manifest must follow once it has been parsed. Every rule is
+/// optional except the magic keyword, which an empty `required-keywords`
+/// turns off.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Policy {
+    pub required_keywords: Vec<String>,
+    /// Every author must be one of these; empty allows anyone.
+    pub allowed_authors: Vec<String>,
+    #[serde(deserialize_with = "deserialize_version")]
+    pub min_rust_version: Option<String>,
+    /// A license the manifest's SPDX expression must offer.
+    pub required_license: Option<String>,
+    pub max_orders: Option<usize>,
+}
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            required_keywords: vec![MAGIC_KEYWORD.to_owned()],
+            allowed_authors: vec![],
+            min_rust_version: None,
+            required_license: None,
+            max_orders: None,
+        }
+    }
+}
+
+/// The first rule a manifest broke.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub code: &'static str,
+    pub message: String,
+}
+impl Violation {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "error": "policy_violation",
+            "code": self.code,
+            "message": self.message,
+        })
+    }
+}
+
+/// The local value of a field that may also be inherited from a workspace,
+/// which cannot be resolved here.
+fn local<T>(field: &Option<MaybeInherited<T>>) -> Option<&T> {
+    field.as_ref().and_then(|field| field.as_ref().as_local())
+}
+
+/// `1.69`, `1.69.0` and `1` as comparable triples.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim().split('.').map(str::parse::<u64>);
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+    match parts.next() {
+        None => Some((major, minor, patch)),
+        Some(_) => None,
+    }
+}
+
+/// Refuses a `min-rust-version` that could never be compared, which would
+/// otherwise turn the rule off without a word.
+fn deserialize_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let version = String::deserialize(deserializer)?;
+    match parse_version(&version) {
+        Some(_) => Ok(Some(version)),
+        None => Err(de::Error::custom(format!("{:?} is not a version", version))),
+    }
+}
+
+/// Whether an SPDX expression such as `MIT OR Apache-2.0` (or the older
+/// `MIT/Apache-2.0`) lets the package be used under `license`.
+fn offers_license(expression: &str, license: &str) -> bool {
+    expression
+        .split('/')
+        .flat_map(|part| part.split(" OR "))
+        .map(|part| part.trim().trim_matches(|c| c == '(' || c == ')'))
+        .any(|part| part.eq_ignore_ascii_case(license))
+}
+
+impl Policy {
+    pub fn check(&self, manifest: &CargoManifest, order_count: usize) -> Result<(), Violation> {
+        let package = manifest.package.as_ref();
+
+        let keywords = package.and_then(|package| local(&package.keywords));
+        for keyword in &self.required_keywords {
+            if !keywords.is_some_and(|keywords| keywords.contains(keyword)) {
+                let message = match keyword.as_str() {
+                    MAGIC_KEYWORD => "Magic keyword not provided".to_owned(),
+                    keyword => format!("Keyword {:?} not provided", keyword),
+                };
+                return Err(Violation::new("missing_keyword", message));
+            }
+        }
+
+        if !self.allowed_authors.is_empty() {
+            let authors = package.and_then(|package| local(&package.authors));
+            let Some(authors) = authors.filter(|authors| !authors.is_empty()) else {
+                return Err(Violation::new("missing_authors", "No authors provided"));
+            };
+            if let Some(author) = authors
+                .iter()
+                .find(|author| !self.allowed_authors.contains(author))
+            {
+                return Err(Violation::new(
+                    "author_not_allowed",
+                    format!("Author {:?} is not allowed", author),
+                ));
+            }
+        }
+
+        if let Some(min_rust_version) = &self.min_rust_version {
+            let Some(rust_version) = package.and_then(|package| local(&package.rust_version))
+            else {
+                return Err(Violation::new(
+                    "missing_rust_version",
+                    "No rust-version provided",
+                ));
+            };
+            let Some(version) = parse_version(rust_version) else {
+                return Err(Violation::new(
+                    "invalid_rust_version",
+                    format!("rust-version {:?} is not a version", rust_version),
+                ));
+            };
+            if parse_version(min_rust_version).is_some_and(|min| version < min) {
+                return Err(Violation::new(
+                    "rust_version_too_old",
+                    format!(
+                        "rust-version {} is older than {}",
+                        rust_version, min_rust_version
+                    ),
+                ));
+            }
+        }
+
+        if let Some(required_license) = &self.required_license {
+            let license = package.and_then(|package| local(&package.license));
+            if !license.is_some_and(|license| offers_license(license, required_license)) {
+                return Err(Violation::new(
+                    "license_required",
+                    format!("License {} is required", required_license),
+                ));
+            }
+        }
+
+        if let Some(max_orders) = self.max_orders {
+            if order_count > max_orders {
+                return Err(Violation::new(
+                    "too_many_orders",
+                    format!(
+                        "At most {} orders are allowed, got {}",
+                        max_orders, order_count
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The policy in force, re-read from `CCH_MANIFEST_POLICY` when the file
+/// changes so that rules can be updated without a redeploy.
+#[derive(Debug, Default)]
+pub struct PolicyState {
+    path: Option<PathBuf>,
+    policy: RwLock<Arc<Policy>>,
+    modified: RwLock<Option<SystemTime>>,
+    /// The last reload failure, so that each one is logged once.
+    failure: RwLock<Option<String>>,
+}
+impl PolicyState {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            ..Self::default()
+        }
+    }
+
+    /// Loads the file named by `CCH_MANIFEST_POLICY` and keeps it fresh by
+    /// polling its modification time every `CCH_MANIFEST_POLICY_RELOAD_SECS`
+    /// seconds, off the request path.
+    pub fn shared_from_env() -> Arc<Self> {
+        let policy_state = Arc::new(Self::new(env::var_os(POLICY_VAR).map(PathBuf::from)));
+        if policy_state.path.is_none() {
+            return policy_state;
+        }
+        policy_state.reload_logged();
+        let interval = env::var(RELOAD_INTERVAL_VAR)
+            .ok()
+            .and_then(|secs| secs.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_RELOAD_INTERVAL);
+        let watched = policy_state.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(interval.max(1))).await;
+                watched.reload_logged();
+            }
+        });
+        policy_state
+    }
+
+    /// Re-reads the file if it changed since the last load, and tells whether
+    /// it did. A file that fails to parse leaves the current policy in place.
+    fn reload(&self) -> Result<bool, String> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if *self.modified.read().unwrap() == Some(modified) {
+            return Ok(false);
+        }
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let policy = toml::from_str::<Policy>(&data)
+            .map_err(|e| format!("{}: {}", path.display(), e.message()))?;
+        *self.policy.write().unwrap() = Arc::new(policy);
+        *self.modified.write().unwrap() = Some(modified);
+        Ok(true)
+    }
+
+    /// Reloads, logging a failure only when it differs from the last one.
+    fn reload_logged(&self) {
+        let failure = self.reload().err();
+        if let Some(e) = &failure {
+            if self.failure.read().unwrap().as_ref() != Some(e) {
+                tracing::warn!(error = %e, "keeping the previous manifest policy");
+            }
+        }
+        *self.failure.write().unwrap() = failure;
+    }
+
+    pub fn current(&self) -> Arc<Policy> {
+        self.policy.read().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(package: &str) -> CargoManifest {
+        toml::from_str(&format!("[package]\nname = \"x\"\n{}", package)).unwrap()
+    }
+
+    fn code(policy: &Policy, package: &str) -> Option<&'static str> {
+        policy
+            .check(&manifest(package), 1)
+            .err()
+            .map(|violation| violation.code)
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = Policy::default();
+        assert_eq!(None, code(&policy, "keywords = [\"Christmas 2024\"]"));
+        let violation = policy
+            .check(&manifest("keywords = [\"Moooooo\"]"), 0)
+            .unwrap_err();
+        assert_eq!("missing_keyword", violation.code);
+        assert_eq!("Magic keyword not provided", violation.message);
+        assert_eq!(
+            Some("missing_keyword"),
+            code(&policy, "keywords.workspace = true")
+        );
+    }
+
+    #[test]
+    fn test_configured_policy() {
+        let policy: Policy = toml::from_str(
+            "required-keywords = []\nallowed-authors = [\"Santa\", \"Elf\"]\nmin-rust-version = \"1.69\"\nrequired-license = \"MIT\"\nmax-orders = 1\n",
+        )
+        .unwrap();
+        let good = "authors = [\"Elf\"]\nrust-version = \"1.70\"\nlicense = \"Apache-2.0 OR MIT\"";
+        assert_eq!(None, code(&policy, good));
+        assert_eq!(
+            Some("author_not_allowed"),
+            code(&policy, &good.replace("[\"Elf\"]", "[\"Elf\", \"Grinch\"]"))
+        );
+        assert_eq!(
+            Some("missing_authors"),
+            code(&policy, &good.replace("[\"Elf\"]", "[]"))
+        );
+        assert_eq!(
+            Some("rust_version_too_old"),
+            code(&policy, &good.replace("1.70", "1.68.2"))
+        );
+        assert_eq!(None, code(&policy, &good.replace("1.70", "1.69")));
+        assert_eq!(
+            Some("invalid_rust_version"),
+            code(&policy, &good.replace("1.70", "latest"))
+        );
+        assert_eq!(
+            Some("license_required"),
+            code(&policy, &good.replace("Apache-2.0 OR MIT", "GPL-3.0"))
+        );
+        let violation = policy.check(&manifest(good), 2).unwrap_err();
+        assert_eq!("too_many_orders", violation.code);
+        assert!(toml::from_str::<Policy>("max-order = 1").is_err());
+        assert!(toml::from_str::<Policy>("min-rust-version = \"1.x\"").is_err());
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = env::temp_dir().join(format!("cch-policy-{}.toml", std::process::id()));
+        let policy_state = PolicyState::new(Some(path.clone()));
+        assert!(policy_state.reload().is_err());
+        assert_eq!(Policy::default(), *policy_state.current());
+
+        fs::write(&path, "max-orders = 3\n").unwrap();
+        assert_eq!(Ok(true), policy_state.reload());
+        assert_eq!(Ok(false), policy_state.reload());
+        assert_eq!(Some(3), policy_state.current().max_orders);
+        fs::write(&path, "max-orders = \"three\"\n").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(policy_state.reload().is_err());
+        assert_eq!(Some(3), policy_state.current().max_orders);
+        fs::remove_file(&path).unwrap();
+    }
+}
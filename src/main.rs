@@ -1,20 +1,25 @@
-use std::{sync::Arc, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
     extract::Request,
     http::StatusCode,
     middleware::{self, Next},
-    response::IntoResponse,
-    routing::{get, post},
+    response::{IntoResponse, Response},
+    routing::{get, patch, post, put},
     Extension, Router,
 };
 
 use ratelimit::Ratelimiter;
-use tokio::time::sleep;
+use shuttle_runtime::{tokio::net::TcpListener, CustomError};
 use tower_cookies::CookieManagerLayer;
 
 mod cch;
 
+use cch::challenge2::{
+    acl::{AccessList, AdminAccess},
+    client::ClientAddr,
+};
+
 pub async fn hello_bird() -> &'static str {
     "Hello, bird!"
 }
@@ -31,9 +36,7 @@ async fn limit_rate(
     next: Next,
 ) -> Result<impl IntoResponse, ()> {
     let state_rate_limiter = &state.rate_limiter;
-    if let Err(sleep_duration) = state_rate_limiter.try_wait() {
-        let _ = sleep(sleep_duration);
-    };
+    let _ = state_rate_limiter.try_wait();
     // println!("M->{}", state_rate_limiter.available());
     if state_rate_limiter.available() != 0 {
         Ok(next.run(request).await)
@@ -41,6 +44,32 @@ async fn limit_rate(
         Ok((StatusCode::BAD_REQUEST, "No milk available\n".to_owned()).into_response())
     }
 }
+/// Refuses requests whose client address the access list denies.
+async fn restrict_source(
+    Extension(access_list): Extension<Arc<AccessList>>,
+    client_addr: ClientAddr,
+    request: Request,
+    next: Next,
+) -> Response {
+    if access_list.check(client_addr.addr).is_allowed() {
+        next.run(request).await
+    } else {
+        (StatusCode::FORBIDDEN, "Forbidden\n".to_owned()).into_response()
+    }
+}
+/// Lets through only the clients `CCH_ADMIN_ACL` allows, nobody by default.
+async fn restrict_admin(
+    Extension(admin_access): Extension<Arc<AdminAccess>>,
+    client_addr: ClientAddr,
+    request: Request,
+    next: Next,
+) -> Response {
+    if admin_access.check(client_addr.addr).is_allowed() {
+        next.run(request).await
+    } else {
+        (StatusCode::FORBIDDEN, "Forbidden\n".to_owned()).into_response()
+    }
+}
 async fn refill(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
     let state_rate_limiter = &state.rate_limiter;
     let _ = state_rate_limiter.set_available(REFILLS);
@@ -48,8 +77,26 @@ async fn refill(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse
     StatusCode::OK
 }
 
+/// Serves the router with `ConnectInfo`, which `shuttle_axum` leaves out, so
+/// that handlers can see the peer address.
+struct CchService(Router);
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for CchService {
+    async fn bind(self, addr: SocketAddr) -> Result<(), shuttle_runtime::Error> {
+        let listener = TcpListener::bind(addr).await.map_err(CustomError::new)?;
+        axum::serve(
+            listener,
+            self.0.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .map_err(CustomError::new)?;
+        Ok(())
+    }
+}
+
 #[shuttle_runtime::main]
-async fn main() -> shuttle_axum::ShuttleAxum {
+async fn main() -> Result<CchService, shuttle_runtime::Error> {
     let rate_limiter = Ratelimiter::builder(1, Duration::from_millis(1000))
         .initial_available(REFILLS)
         .max_tokens(REFILLS)
@@ -63,10 +110,107 @@ async fn main() -> shuttle_axum::ShuttleAxum {
         .route("/2/key", get(cch::challenge2::calc_ip_ops))
         .route("/2/v6/dest", get(cch::challenge2::calc_ip_ops))
         .route("/2/v6/key", get(cch::challenge2::calc_ip_ops))
+        .route("/2/v6/eui64", get(cch::challenge2::slaac::eui64_addr))
+        .route("/2/v6/mac", get(cch::challenge2::slaac::eui64_to_mac))
+        .route("/2/v6/stable", get(cch::challenge2::slaac::stable_addr))
+        .route(
+            "/2/key/recover",
+            post(cch::challenge2::recover::recover_key),
+        )
+        .route("/2/batch", post(cch::challenge2::batch_ip_ops))
+        .route("/2/whoami", get(cch::challenge2::client::whoami))
+        .route("/2/acl/check", get(cch::challenge2::acl::check_acl))
+        .route(
+            "/2/lookup",
+            get(cch::challenge2::lookup::lookup_addr).post(cch::challenge2::lookup::lookup_addrs),
+        )
+        .layer(Extension(
+            cch::challenge2::lookup::LookupState::shared_from_env(),
+        ))
+        .route("/2/inspect", get(cch::challenge2::inspect::inspect_addr))
+        .route("/2/ipam/pools", get(cch::challenge2::ipam::list_pools))
+        .route(
+            "/2/ipam/pools/:name",
+            get(cch::challenge2::ipam::show_pool)
+                .post(cch::challenge2::ipam::create_pool)
+                .delete(cch::challenge2::ipam::delete_pool),
+        )
+        .route(
+            "/2/ipam/pools/:name/allocate",
+            post(cch::challenge2::ipam::allocate),
+        )
+        .route(
+            "/2/ipam/pools/:name/reserve",
+            post(cch::challenge2::ipam::reserve),
+        )
+        .route(
+            "/2/ipam/pools/:name/release",
+            post(cch::challenge2::ipam::release),
+        )
+        .layer(Extension(
+            cch::challenge2::ipam::IpamState::rwlocked_default(),
+        ))
+        .route("/2/range/expand", get(cch::challenge2::range::expand_range))
+        .route(
+            "/2/range/compress",
+            post(cch::challenge2::range::compress_range),
+        )
+        .route("/2/subnet", get(cch::challenge2::subnet::subnet_info))
+        .route(
+            "/2/subnet/split",
+            get(cch::challenge2::subnet::split_subnet),
+        )
+        .route(
+            "/2/subnet/aggregate",
+            post(cch::challenge2::subnet::aggregate_subnets),
+        )
         .route("/5/manifest", post(cch::challenge5::manifest_messaging))
+        .route(
+            "/5/convert",
+            post(cch::challenge5::convert::convert_manifest),
+        )
+        .route(
+            "/5/invoice",
+            post(cch::challenge5::invoice::invoice_manifest),
+        )
+        .route("/5/orders", get(cch::challenge5::book::list_orders))
+        .route(
+            "/5/orders/:id",
+            get(cch::challenge5::book::show_order).merge(
+                patch(cch::challenge5::book::amend_order)
+                    .delete(cch::challenge5::book::cancel_order)
+                    .layer(middleware::from_fn(restrict_admin)),
+            ),
+        )
+        .layer(Extension(
+            cch::challenge5::policy::PolicyState::shared_from_env(),
+        ))
+        .route("/5/catalog", get(cch::challenge5::catalog::show_catalog))
+        .route(
+            "/5/catalog/:item",
+            put(cch::challenge5::catalog::set_stock)
+                .delete(cch::challenge5::catalog::remove_item)
+                .layer(middleware::from_fn(restrict_admin)),
+        )
+        .route(
+            "/5/catalog/:item/restock",
+            post(cch::challenge5::catalog::restock_item).layer(middleware::from_fn(restrict_admin)),
+        )
+        .layer(Extension(
+            cch::challenge5::book::OrderBook::shared_from_env(),
+        ))
+        .layer(Extension(
+            cch::challenge5::catalog::Catalog::shared_from_env(),
+        ))
+        .layer(Extension(
+            cch::challenge5::invoice::Pricing::shared_from_env(),
+        ))
         .route("/9/milk", post(cch::challenge9::milk))
         .route("/12/board", get(cch::challenge12::show_board))
-        .route("/12/reset", post(cch::challenge12::reset_board))
+        .route(
+            "/12/reset",
+            post(cch::challenge12::reset_board).layer(middleware::from_fn(restrict_source)),
+        )
         .route("/12/place/:team/:column", post(cch::challenge12::place))
         .route("/12/random-board", get(cch::challenge12::randomize_board))
         .layer(Extension(cch::challenge12::BoardState::rwlocked_default()))
@@ -75,8 +219,19 @@ async fn main() -> shuttle_axum::ShuttleAxum {
         .route("/16/decode", post(cch::challenge16::decode))
         .layer(CookieManagerLayer::new())
         .layer(middleware::from_fn(limit_rate))
-        .route("/9/refill", post(refill))
-        .layer(Extension(shared_state));
+        .route(
+            "/9/refill",
+            post(refill).layer(middleware::from_fn(restrict_source)),
+        )
+        .layer(Extension(shared_state))
+        .layer(Extension(AccessList::shared_from_env()))
+        .layer(Extension(AdminAccess::shared_from_env()))
+        .layer(Extension(
+            cch::challenge2::client::ProxyTrust::shared_from_env(),
+        ))
+        .layer(Extension(
+            cch::challenge2::slaac::StableSecret::shared_from_env(),
+        ));
 
-    Ok(router.into())
+    Ok(CchService(router))
 }